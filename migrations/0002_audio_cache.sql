@@ -0,0 +1,10 @@
+CREATE TABLE
+  IF NOT EXISTS audio_cache (
+    unique_id TEXT NOT NULL PRIMARY KEY,
+    file_id TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    duration_ms INTEGER,
+    checksum TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch ())
+  );
@@ -0,0 +1,6 @@
+ALTER TABLE audio_cache
+ADD COLUMN last_used_at INTEGER NOT NULL DEFAULT 0;
+
+UPDATE audio_cache
+SET
+  last_used_at = created_at;
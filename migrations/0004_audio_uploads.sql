@@ -0,0 +1,9 @@
+CREATE TABLE
+  IF NOT EXISTS audio_uploads (
+    unique_id TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch ()),
+    PRIMARY KEY (unique_id, user_id)
+  );
+
+CREATE INDEX IF NOT EXISTS audio_uploads_user_id ON audio_uploads (user_id);
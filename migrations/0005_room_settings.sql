@@ -0,0 +1,8 @@
+ALTER TABLE rooms
+ADD COLUMN start_delay INTEGER;
+
+ALTER TABLE rooms
+ADD COLUMN player_command TEXT;
+
+ALTER TABLE rooms
+ADD COLUMN volume INTEGER;
@@ -0,0 +1,9 @@
+CREATE TABLE
+  IF NOT EXISTS speech (
+    unique_id TEXT NOT NULL PRIMARY KEY,
+    text TEXT NOT NULL,
+    voice TEXT
+  );
+
+ALTER TABLE rooms
+ADD COLUMN tts_voice TEXT;
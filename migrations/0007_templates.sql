@@ -0,0 +1,5 @@
+CREATE TABLE
+  IF NOT EXISTS templates (
+    name TEXT NOT NULL PRIMARY KEY,
+    text TEXT NOT NULL
+  );
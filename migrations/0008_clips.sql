@@ -0,0 +1,9 @@
+CREATE TABLE
+  IF NOT EXISTS clips (
+    name TEXT NOT NULL PRIMARY KEY,
+    unique_id TEXT NOT NULL,
+    created_by INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch ())
+  );
+
+CREATE INDEX IF NOT EXISTS clips_unique_id ON clips (unique_id);
@@ -0,0 +1,11 @@
+CREATE TABLE
+  IF NOT EXISTS schedules (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    unique_id TEXT NOT NULL,
+    room_name TEXT NOT NULL,
+    run_at INTEGER NOT NULL,
+    created_by INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch ())
+  );
+
+CREATE INDEX IF NOT EXISTS schedules_run_at ON schedules (run_at);
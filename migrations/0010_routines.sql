@@ -0,0 +1,13 @@
+CREATE TABLE
+  IF NOT EXISTS routines (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    clip_name TEXT NOT NULL,
+    rooms TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    next_run_at INTEGER,
+    created_by INTEGER NOT NULL
+  );
+
+CREATE TABLE
+  IF NOT EXISTS holidays (date TEXT NOT NULL PRIMARY KEY);
@@ -0,0 +1,9 @@
+CREATE TABLE
+  IF NOT EXISTS room_groups (name TEXT NOT NULL PRIMARY KEY, preset INTEGER);
+
+CREATE TABLE
+  IF NOT EXISTS room_group_members (
+    group_name TEXT NOT NULL,
+    room_name TEXT NOT NULL,
+    PRIMARY KEY (group_name, room_name)
+  );
@@ -0,0 +1,2 @@
+ALTER TABLE rooms
+ADD COLUMN location TEXT;
@@ -0,0 +1,16 @@
+ALTER TABLE keyboard_buttons
+ADD COLUMN text TEXT NOT NULL DEFAULT '';
+
+ALTER TABLE keyboard_buttons
+ADD COLUMN row_index INTEGER;
+
+CREATE TABLE
+  IF NOT EXISTS keyboard_pages (
+    message_id INTEGER NOT NULL PRIMARY KEY,
+    chunk_size INTEGER NOT NULL,
+    page_size INTEGER NOT NULL,
+    page INTEGER NOT NULL
+  );
+
+ALTER TABLE rooms
+ADD COLUMN sort_index INTEGER NOT NULL DEFAULT 0;
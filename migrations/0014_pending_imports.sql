@@ -0,0 +1,9 @@
+CREATE TABLE
+  IF NOT EXISTS pending_imports (
+    message_id INTEGER NOT NULL,
+    clip_name TEXT NOT NULL,
+    room_name TEXT NOT NULL,
+    run_at INTEGER NOT NULL
+  );
+
+CREATE INDEX IF NOT EXISTS pending_imports_message_id ON pending_imports (message_id);
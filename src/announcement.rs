@@ -0,0 +1,236 @@
+use std::{collections::BTreeSet, error::Error};
+
+use itertools::Itertools;
+use sqlx::{Pool, Sqlite};
+use teloxide::Bot;
+
+use crate::{
+    audio::{
+        decode::DecodeAudioError,
+        processor::{AudioProcessor, ProcessAudioError, ProcessedAudio},
+    },
+    audio_cache::{fetch_audio, AudioCacheEntry},
+    config::{AppConfig, CombinedPreset},
+    player::PlayerOverrides,
+    room_group::RoomGroup,
+    tts::{synthesize, Speech},
+};
+
+/// A room with everything needed to play an announcement in it.
+pub struct Room {
+    pub name: String,
+    pub preset: i64,
+    pub overrides: PlayerOverrides,
+    pub tts_voice: Option<String>,
+}
+
+impl Room {
+    pub async fn find(db: &Pool<Sqlite>, name: &str) -> sqlx::Result<Option<Room>> {
+        let row = sqlx::query!(
+            "SELECT name, preset, start_delay, player_command, volume, tts_voice
+                FROM rooms WHERE name = ?",
+            name
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(row.map(|row| Room {
+            name: row.name,
+            preset: row.preset,
+            overrides: PlayerOverrides {
+                start_delay: row.start_delay.map(|start_delay| start_delay as u64),
+                player_command: row.player_command,
+                volume: row.volume.map(|volume| volume as u32),
+            },
+            tts_voice: row.tts_voice,
+        }))
+    }
+
+    /// A group or combined preset playing in all rooms at once.
+    fn combined(name: String, preset: i64) -> Room {
+        Room {
+            name,
+            preset,
+            overrides: PlayerOverrides::default(),
+            tts_voice: None,
+        }
+    }
+
+    /// The rooms to play in one after another. A group or combined preset covering exactly
+    /// the selection plays in all of them at once instead. Rooms which are gone are left out.
+    pub async fn resolve(
+        db: &Pool<Sqlite>,
+        combined_presets: &[CombinedPreset],
+        room_names: &[String],
+    ) -> sqlx::Result<Vec<Room>> {
+        let selection: BTreeSet<&String> = room_names.iter().collect();
+        if selection.len() > 1 {
+            let group = RoomGroup::all(db).await?.into_iter().find(|group| {
+                group.preset.is_some() && group.rooms.iter().collect::<BTreeSet<_>>() == selection
+            });
+            if let Some(RoomGroup {
+                name,
+                preset: Some(preset),
+                ..
+            }) = group
+            {
+                return Ok(vec![Room::combined(name, preset)]);
+            }
+            let combined = combined_presets.iter().find(|combined_preset| {
+                combined_preset.rooms.iter().collect::<BTreeSet<_>>() == selection
+            });
+            if let Some(combined) = combined {
+                return Ok(vec![Room::combined(
+                    combined.rooms.iter().join(" + "),
+                    combined.preset.into(),
+                )]);
+            }
+        }
+
+        let mut rooms = Vec::new();
+        for room_name in room_names {
+            match Room::find(db, room_name).await? {
+                Some(room) => rooms.push(room),
+                None => log::warn!("skipping room {} which no longer exists", room_name),
+            }
+        }
+        Ok(rooms)
+    }
+}
+
+/// Where to play an announcement, as picked on the keyboard.
+pub enum Target {
+    Rooms(Vec<String>),
+    Group(String),
+}
+
+impl Target {
+    pub fn label(&self) -> String {
+        match self {
+            Target::Rooms(room_names) => room_names.join(", "),
+            Target::Group(name) => name.to_owned(),
+        }
+    }
+
+    /// The rooms to play in one after another, empty if they are gone.
+    pub async fn resolve(
+        &self,
+        db: &Pool<Sqlite>,
+        app_config: &AppConfig,
+    ) -> sqlx::Result<Vec<Room>> {
+        match self {
+            Target::Rooms(room_names) => {
+                Room::resolve(db, &app_config.combined_presets, room_names).await
+            }
+            Target::Group(name) => match RoomGroup::find(db, name).await? {
+                Some(RoomGroup {
+                    name,
+                    preset: Some(preset),
+                    ..
+                }) => Ok(vec![Room::combined(name, preset)]),
+                Some(group) => Room::resolve(db, &app_config.combined_presets, &group.rooms).await,
+                None => Ok(Vec::new()),
+            },
+        }
+    }
+}
+
+/// Returns the file to play in the room, downloading or synthesizing it again if it got lost
+/// or truncated. Speech is read out in the voice of the room.
+pub async fn prepare_audio(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    audio_processor: &AudioProcessor,
+    entry: &AudioCacheEntry,
+    room: &Room,
+) -> Result<(AudioCacheEntry, ProcessedAudio), Box<dyn Error + Send + Sync>> {
+    let entry = match Speech::find(db, &entry.unique_id).await? {
+        Some(speech) => synthesize(db, app_config, &speech, room.tts_voice.as_deref()).await?,
+        None => {
+            fetch_audio(
+                bot,
+                db,
+                &app_config.audio_dir,
+                &entry.file_id,
+                &entry.unique_id,
+                None,
+            )
+            .await?
+        }
+    };
+    let dst_path = entry.path(&app_config.audio_dir);
+
+    let processed = match audio_processor.process(&dst_path, &room.name).await {
+        Ok(processed) => processed,
+        Err(err) => {
+            match err {
+                // warned about on startup already
+                ProcessAudioError::Decode(DecodeAudioError::Unsupported) => {
+                    log::debug!("can't decode audio, playing it unprocessed")
+                }
+                err => log::error!("failed to process audio, playing it unprocessed: {}", err),
+            }
+            ProcessedAudio {
+                path: dst_path,
+                duration: None,
+            }
+        }
+    };
+    Ok((entry, processed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    async fn db_with_rooms() -> Pool<Sqlite> {
+        let db = db::memory().await;
+        sqlx::query(
+            "INSERT INTO rooms (name, preset) VALUES ('Hall', 1), ('Yard', 2), ('Office', 3)",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        db
+    }
+
+    /// Resolves the selection, describing each room as `name=preset`.
+    async fn resolve(
+        db: &Pool<Sqlite>,
+        combined_presets: &[CombinedPreset],
+        room_names: &[&str],
+    ) -> Vec<String> {
+        let room_names: Vec<String> = room_names.iter().map(|&name| name.into()).collect();
+        Room::resolve(db, combined_presets, &room_names)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|room| format!("{}={}", room.name, room.preset))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn combined_preset_covers_exact_selection() {
+        let db = db_with_rooms().await;
+        let combined_presets = ["Hall+Yard=12".parse().unwrap()];
+
+        assert_eq!(
+            resolve(&db, &combined_presets, &["Yard", "Hall"]).await,
+            ["Hall + Yard=12"]
+        );
+        assert_eq!(
+            resolve(&db, &combined_presets, &["Hall", "Yard", "Office"]).await,
+            ["Hall=1", "Yard=2", "Office=3"]
+        );
+        assert_eq!(
+            resolve(&db, &combined_presets, &["Office", "Hall"]).await,
+            ["Office=3", "Hall=1"]
+        );
+        assert_eq!(resolve(&db, &combined_presets, &["Hall"]).await, ["Hall=1"]);
+        assert_eq!(
+            resolve(&db, &combined_presets, &["Gone", "Yard"]).await,
+            ["Yard=2"]
+        );
+    }
+}
@@ -0,0 +1,56 @@
+pub mod decode;
+pub mod dsp;
+pub mod loudness;
+pub mod probe;
+pub mod processor;
+pub mod silence;
+pub mod wav;
+
+use std::time::Duration;
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64().round() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+const PROGRESS_BAR_WIDTH: usize = 10;
+
+/// A text progress bar like `▰▰▰▱▱▱▱▱▱▱ 0:12 / 0:40`.
+pub fn format_progress(elapsed: Duration, total: Duration) -> String {
+    let elapsed = elapsed.min(total);
+    let filled = match total.as_secs_f64() {
+        0.0 => PROGRESS_BAR_WIDTH,
+        total_secs => {
+            (elapsed.as_secs_f64() / total_secs * PROGRESS_BAR_WIDTH as f64).round() as usize
+        }
+    };
+    format!(
+        "{}{} {} / {}",
+        "▰".repeat(filled),
+        "▱".repeat(PROGRESS_BAR_WIDTH - filled),
+        format_duration(elapsed),
+        format_duration(total)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_bar() {
+        let total = Duration::from_secs(40);
+        assert_eq!(
+            format_progress(Duration::ZERO, total),
+            "▱▱▱▱▱▱▱▱▱▱ 0:00 / 0:40"
+        );
+        assert_eq!(
+            format_progress(Duration::from_secs(12), total),
+            "▰▰▰▱▱▱▱▱▱▱ 0:12 / 0:40"
+        );
+        assert_eq!(
+            format_progress(Duration::from_secs(45), total),
+            "▰▰▰▰▰▰▰▰▰▰ 0:40 / 0:40"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fs::File, io, path::Path};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DecodeAudioError {
+    #[error("failed to open audio file")]
+    Io(#[from] io::Error),
+    #[error("no decodable audio track found")]
+    NoTrack,
+    #[error("unsupported audio format")]
+    Unsupported,
+    #[error("failed to decode audio: {0}")]
+    Symphonia(SymphoniaError),
+}
+
+impl From<SymphoniaError> for DecodeAudioError {
+    fn from(err: SymphoniaError) -> Self {
+        match err {
+            SymphoniaError::Unsupported(_) => Self::Unsupported,
+            SymphoniaError::IoError(err) => Self::Io(err),
+            err => Self::Symphonia(err),
+        }
+    }
+}
+
+/// Interleaved 32-bit float samples of a fully decoded audio file.
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl DecodedAudio {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    /// Synthesizes a sine tone at 48kHz, used to verify processing without audio files.
+    #[cfg(test)]
+    pub fn sine(frequency: f64, amplitude: f64, secs: f64, channels: usize) -> Self {
+        let sample_rate = 48000;
+        let frames = (secs * sample_rate as f64) as usize;
+        let samples = (0..frames)
+            .flat_map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let value = (amplitude * (2.0 * std::f64::consts::PI * frequency * t).sin()) as f32;
+                std::iter::repeat_n(value, channels)
+            })
+            .collect();
+        DecodedAudio {
+            sample_rate,
+            channels,
+            samples,
+        }
+    }
+}
+
+pub fn decode_file(path: &Path) -> Result<DecodedAudio, DecodeAudioError> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(DecodeAudioError::NoTrack)?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut channels = track.codec_params.channels.map(|channels| channels.count());
+    let mut samples = Vec::new();
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(err) => return Err(err.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(err)) => {
+                log::warn!("skipping undecodable audio packet: {}", err);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let spec = *decoded.spec();
+        sample_rate.get_or_insert(spec.rate);
+        channels.get_or_insert(spec.channels.count());
+
+        let buf = match &mut sample_buf {
+            Some(buf) if buf.capacity() >= decoded.capacity() * spec.channels.count() => buf,
+            buf => buf.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buf.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buf.samples());
+    }
+
+    let (Some(sample_rate), Some(channels)) = (sample_rate, channels) else {
+        return Err(DecodeAudioError::NoTrack);
+    };
+    if channels == 0 {
+        return Err(DecodeAudioError::NoTrack);
+    }
+
+    Ok(DecodedAudio {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
@@ -0,0 +1,428 @@
+//! Processing chain improving speech intelligibility on small PA speakers.
+
+use std::{collections::HashMap, error::Error, f64::consts::PI, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::decode::DecodedAudio;
+
+pub fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f64) -> f64 {
+    20.0 * gain.log10()
+}
+
+const COMPRESSOR_RMS_MS: f64 = 10.0;
+
+/// Coefficient of a one-pole smoothing filter reaching ~63% of a step after `ms`.
+fn smoothing_coefficient(ms: f64, sample_rate: u32) -> f64 {
+    if ms <= 0.0 {
+        return 1.0;
+    }
+    1.0 - (-1000.0 / (ms * sample_rate as f64)).exp()
+}
+
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad { b, a, z: [0.0; 2] }
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    pub fn high_pass(sample_rate: u32, frequency: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn peaking(sample_rate: u32, frequency: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+fn apply_biquad(audio: &mut DecodedAudio, filter: Biquad) {
+    let mut filters = vec![filter; audio.channels];
+    for frame in audio.samples.chunks_exact_mut(audio.channels) {
+        for (sample, filter) in frame.iter_mut().zip(filters.iter_mut()) {
+            *sample = filter.process(*sample as f64) as f32;
+        }
+    }
+}
+
+/// Applies a gain curve computed from the loudest channel of every frame.
+fn apply_linked_gain(audio: &mut DecodedAudio, mut gain: impl FnMut(f64) -> f64) {
+    for frame in audio.samples.chunks_exact_mut(audio.channels) {
+        let level = frame.iter().fold(0.0f64, |max, s| max.max(s.abs() as f64));
+        let gain = gain(level) as f32;
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+}
+
+/// Removes low-frequency rumble and handling noise.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighPass {
+    pub frequency: f64,
+}
+
+impl Default for HighPass {
+    fn default() -> Self {
+        HighPass { frequency: 100.0 }
+    }
+}
+
+impl HighPass {
+    pub fn apply(&self, audio: &mut DecodedAudio) {
+        let filter = Biquad::high_pass(audio.sample_rate, self.frequency, 0.5f64.sqrt());
+        apply_biquad(audio, filter);
+    }
+}
+
+/// Boosts the frequency range carrying consonants.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresenceEq {
+    pub frequency: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl Default for PresenceEq {
+    fn default() -> Self {
+        PresenceEq {
+            frequency: 3000.0,
+            gain_db: 4.0,
+            q: 1.0,
+        }
+    }
+}
+
+impl PresenceEq {
+    pub fn apply(&self, audio: &mut DecodedAudio) {
+        let filter = Biquad::peaking(audio.sample_rate, self.frequency, self.q, self.gain_db);
+        apply_biquad(audio, filter);
+    }
+}
+
+/// Attenuates background noise in the pauses between words.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseGate {
+    pub threshold_db: f64,
+    pub range_db: f64,
+    pub attack_ms: f64,
+    pub hold_ms: f64,
+    pub release_ms: f64,
+}
+
+impl Default for NoiseGate {
+    fn default() -> Self {
+        NoiseGate {
+            threshold_db: -50.0,
+            range_db: -40.0,
+            attack_ms: 1.0,
+            hold_ms: 100.0,
+            release_ms: 150.0,
+        }
+    }
+}
+
+impl NoiseGate {
+    pub fn apply(&self, audio: &mut DecodedAudio) {
+        let threshold = db_to_gain(self.threshold_db);
+        let floor = db_to_gain(self.range_db);
+        let attack = smoothing_coefficient(self.attack_ms, audio.sample_rate);
+        let release = smoothing_coefficient(self.release_ms, audio.sample_rate);
+        let hold_frames = (self.hold_ms * audio.sample_rate as f64 / 1000.0) as usize;
+
+        let mut held = 0;
+        let mut gain = floor;
+        apply_linked_gain(audio, |level| {
+            if level >= threshold {
+                held = hold_frames;
+            } else {
+                held = held.saturating_sub(1);
+            }
+            let (target, coefficient) = if held > 0 {
+                (1.0, attack)
+            } else {
+                (floor, release)
+            };
+            gain += (target - gain) * coefficient;
+            gain
+        });
+    }
+}
+
+/// Evens out the level differences between quiet and loud speakers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Compressor {
+    pub threshold_db: f64,
+    pub ratio: f64,
+    pub attack_ms: f64,
+    pub release_ms: f64,
+    pub makeup_db: f64,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Compressor {
+            threshold_db: -24.0,
+            ratio: 3.0,
+            attack_ms: 10.0,
+            release_ms: 200.0,
+            makeup_db: 6.0,
+        }
+    }
+}
+
+impl Compressor {
+    pub fn apply(&self, audio: &mut DecodedAudio) {
+        let detector = smoothing_coefficient(COMPRESSOR_RMS_MS, audio.sample_rate);
+        let attack = smoothing_coefficient(self.attack_ms, audio.sample_rate);
+        let release = smoothing_coefficient(self.release_ms, audio.sample_rate);
+        let makeup = db_to_gain(self.makeup_db);
+        let ratio = self.ratio.max(1.0);
+
+        // the gain reduction follows the rms level, so a steady tone is compressed evenly
+        let mut power = 0.0;
+        let mut reduction_db = 0.0;
+        apply_linked_gain(audio, |level| {
+            power += (level * level - power) * detector;
+
+            let over = 10.0 * power.max(1e-12).log10() - self.threshold_db;
+            let target_db = if over > 0.0 { over / ratio - over } else { 0.0 };
+            let coefficient = if target_db < reduction_db {
+                attack
+            } else {
+                release
+            };
+            reduction_db += (target_db - reduction_db) * coefficient;
+
+            db_to_gain(reduction_db) * makeup
+        });
+    }
+}
+
+/// A chain of optional processing stages, applied in the order high-pass, gate, EQ, compressor.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspProfile {
+    pub high_pass: Option<HighPass>,
+    pub gate: Option<NoiseGate>,
+    pub presence: Option<PresenceEq>,
+    pub compressor: Option<Compressor>,
+}
+
+impl DspProfile {
+    pub fn apply(&self, audio: &mut DecodedAudio) {
+        if let Some(high_pass) = &self.high_pass {
+            high_pass.apply(audio);
+        }
+        if let Some(gate) = &self.gate {
+            gate.apply(audio);
+        }
+        if let Some(presence) = &self.presence {
+            presence.apply(audio);
+        }
+        if let Some(compressor) = &self.compressor {
+            compressor.apply(audio);
+        }
+    }
+}
+
+/// Named processing profiles and which rooms use them, read from `DSP_PROFILES_FILE`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DspProfiles {
+    pub default: Option<String>,
+    pub rooms: HashMap<String, String>,
+    pub profiles: HashMap<String, DspProfile>,
+}
+
+impl DspProfiles {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let profiles: DspProfiles = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let unknown = profiles
+            .default
+            .iter()
+            .chain(profiles.rooms.values())
+            .find(|name| !profiles.profiles.contains_key(*name));
+        if let Some(name) = unknown {
+            return Err(format!("dsp profile {} is not defined", name).into());
+        }
+        Ok(profiles)
+    }
+
+    /// Returns the name and settings of the profile used for a room, if any.
+    pub fn for_room(&self, room_name: &str) -> Option<(&str, &DspProfile)> {
+        let name = self.rooms.get(room_name).or(self.default.as_ref())?;
+        self.profiles
+            .get(name)
+            .map(|profile| (name.as_str(), profile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms_db(audio: &DecodedAudio, from_secs: f64, to_secs: f64) -> f64 {
+        let frame_index = |secs: f64| (secs * audio.sample_rate as f64) as usize * audio.channels;
+        let samples = &audio.samples[frame_index(from_secs)..frame_index(to_secs)];
+        let power = samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64;
+        10.0 * power.log10()
+    }
+
+    #[test]
+    fn high_pass_removes_rumble() {
+        let high_pass = HighPass::default();
+
+        let mut rumble = DecodedAudio::sine(25.0, 0.5, 2.0, 1);
+        let before = rms_db(&rumble, 0.5, 2.0);
+        high_pass.apply(&mut rumble);
+        assert!(
+            rms_db(&rumble, 0.5, 2.0) < before - 20.0,
+            "rumble not attenuated"
+        );
+
+        let mut speech = DecodedAudio::sine(1000.0, 0.5, 2.0, 1);
+        let before = rms_db(&speech, 0.5, 2.0);
+        high_pass.apply(&mut speech);
+        assert!(
+            (rms_db(&speech, 0.5, 2.0) - before).abs() < 0.5,
+            "speech attenuated"
+        );
+    }
+
+    #[test]
+    fn presence_boosts_center_frequency() {
+        let presence = PresenceEq::default();
+
+        let mut tone = DecodedAudio::sine(presence.frequency, 0.1, 2.0, 2);
+        let before = rms_db(&tone, 0.5, 2.0);
+        presence.apply(&mut tone);
+        let boost = rms_db(&tone, 0.5, 2.0) - before;
+        assert!(
+            (boost - presence.gain_db).abs() < 0.2,
+            "boosted by {}dB",
+            boost
+        );
+
+        let mut low = DecodedAudio::sine(150.0, 0.1, 2.0, 2);
+        let before = rms_db(&low, 0.5, 2.0);
+        presence.apply(&mut low);
+        assert!(
+            (rms_db(&low, 0.5, 2.0) - before).abs() < 0.5,
+            "low tone changed"
+        );
+    }
+
+    #[test]
+    fn gate_mutes_noise_between_words() {
+        let gate = NoiseGate::default();
+        let words = DecodedAudio::sine(500.0, 0.3, 1.0, 1);
+        let noise = DecodedAudio::sine(6000.0, db_to_gain(-60.0), 2.0, 1);
+        let mut audio = DecodedAudio {
+            samples: [words.samples.clone(), noise.samples.clone()].concat(),
+            ..words
+        };
+        gate.apply(&mut audio);
+
+        let level = rms_db(&audio, 0.1, 1.0);
+        assert!(
+            (level - rms_db(&words, 0.1, 1.0)).abs() < 0.5,
+            "words at {}dB",
+            level
+        );
+        let attenuation = rms_db(&noise, 1.5, 2.0) - rms_db(&audio, 2.5, 3.0);
+        assert!(attenuation > 30.0, "noise attenuated by {}dB", attenuation);
+    }
+
+    #[test]
+    fn compressor_reduces_loud_tone() {
+        let compressor = Compressor {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            makeup_db: 0.0,
+            ..Compressor::default()
+        };
+
+        // a sine with -10dBFS rms is 10dB over the threshold and should leave 2.5dB of it
+        let mut loud = DecodedAudio::sine(1000.0, db_to_gain(-10.0) * 2f64.sqrt(), 3.0, 2);
+        compressor.apply(&mut loud);
+        let level = rms_db(&loud, 1.0, 3.0);
+        assert!((level + 17.5).abs() < 1.0, "compressed to {}dB", level);
+
+        let mut quiet = DecodedAudio::sine(1000.0, db_to_gain(-30.0) * 2f64.sqrt(), 3.0, 2);
+        compressor.apply(&mut quiet);
+        let level = rms_db(&quiet, 1.0, 3.0);
+        assert!(
+            (level + 30.0).abs() < 0.1,
+            "quiet tone changed to {}dB",
+            level
+        );
+    }
+
+    #[test]
+    fn profiles_for_rooms() {
+        let profiles: DspProfiles = serde_json::from_str(
+            r#"{
+                "default": "speech",
+                "rooms": { "Hall": "loud" },
+                "profiles": {
+                    "speech": { "high_pass": {} },
+                    "loud": { "compressor": { "ratio": 6 } }
+                }
+            }"#,
+        )
+        .expect("invalid profiles");
+
+        let (name, profile) = profiles.for_room("Hall").expect("no profile for hall");
+        assert_eq!(name, "loud");
+        assert_eq!(profile.compressor.as_ref().map(|c| c.ratio), Some(6.0));
+        assert_eq!(
+            profile.compressor.as_ref().map(|c| c.threshold_db),
+            Some(-24.0)
+        );
+
+        let (name, profile) = profiles.for_room("Kitchen").expect("no default profile");
+        assert_eq!(name, "speech");
+        assert!(profile.high_pass.is_some() && profile.compressor.is_none());
+    }
+}
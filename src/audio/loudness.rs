@@ -0,0 +1,244 @@
+//! Loudness measurement after EBU R128 / ITU-R BS.1770 and a true-peak limiter.
+
+use std::f64::consts::PI;
+
+use super::{
+    decode::DecodedAudio,
+    dsp::{db_to_gain, gain_to_db, Biquad},
+};
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+const LIMITER_LOOKAHEAD_SECS: f64 = 0.005;
+const LIMITER_RELEASE_SECS: f64 = 0.05;
+
+/// The two stage K-weighting pre-filter, with the coefficients derived for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / fs).tan();
+    let vh = db_to_gain(g);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [shelf, high_pass]
+}
+
+fn channel_weights(channels: usize) -> Vec<f64> {
+    // 5.1 layouts exclude the LFE channel and weight the surround channels up
+    if channels == 6 {
+        vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+    } else {
+        vec![1.0; channels]
+    }
+}
+
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Measures the gated integrated loudness in LUFS, or `None` for digital silence.
+pub fn integrated_loudness(audio: &DecodedAudio) -> Option<f64> {
+    let channels = audio.channels;
+    let frames = audio.frames();
+    if frames == 0 {
+        return None;
+    }
+
+    let mut filters: Vec<[Biquad; 2]> = (0..channels)
+        .map(|_| k_weighting(audio.sample_rate))
+        .collect();
+    let weights = channel_weights(channels);
+
+    // weighted squares of the filtered signal, summed over 100ms steps
+    let step = (audio.sample_rate as usize / 10).max(1);
+    let mut step_powers = vec![0.0; frames.div_ceil(step)];
+    for (frame_index, frame) in audio.samples.chunks_exact(channels).enumerate() {
+        let mut power = 0.0;
+        for (channel, &sample) in frame.iter().enumerate() {
+            let [shelf, high_pass] = &mut filters[channel];
+            let y = high_pass.process(shelf.process(sample as f64));
+            power += weights[channel] * y * y;
+        }
+        step_powers[frame_index / step] += power;
+    }
+
+    // 400ms blocks overlapping by 75%, or a single block for very short clips
+    let block_steps = 4.min(step_powers.len());
+    let block_powers: Vec<f64> = step_powers
+        .windows(block_steps)
+        .map(|steps| steps.iter().sum::<f64>() / (block_steps * step) as f64)
+        .collect();
+
+    let mean_power = |threshold: f64| -> Option<f64> {
+        let gated: Vec<f64> = block_powers
+            .iter()
+            .copied()
+            .filter(|&power| power > 0.0 && block_loudness(power) > threshold)
+            .collect();
+        if gated.is_empty() {
+            None
+        } else {
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        }
+    };
+
+    let relative_threshold = block_loudness(mean_power(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+    mean_power(relative_threshold.max(ABSOLUTE_GATE)).map(block_loudness)
+}
+
+/// Windowed sinc low-pass used to interpolate between samples, split into polyphase branches.
+fn oversampling_filter() -> [[f64; TAPS_PER_PHASE]; OVERSAMPLING] {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let center = (len - 1) as f64 / 2.0;
+    let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+    for i in 0..len {
+        let t = (i as f64 - center) / OVERSAMPLING as f64;
+        let sinc = if t == 0.0 {
+            1.0
+        } else {
+            (PI * t).sin() / (PI * t)
+        };
+        let window = 0.5 - 0.5 * (2.0 * PI * (i as f64 + 0.5) / len as f64).cos();
+        phases[i % OVERSAMPLING][i / OVERSAMPLING] = sinc * window;
+    }
+    phases
+}
+
+/// Per frame estimate of the highest absolute sample value after 4x oversampling.
+fn true_peak_envelope(audio: &DecodedAudio) -> Vec<f64> {
+    let channels = audio.channels;
+    let phases = oversampling_filter();
+    let mut envelope = vec![0.0f64; audio.frames()];
+    let mut history = vec![[0.0f64; TAPS_PER_PHASE]; channels];
+
+    for (frame_index, frame) in audio.samples.chunks_exact(channels).enumerate() {
+        for (channel, &sample) in frame.iter().enumerate() {
+            let history = &mut history[channel];
+            history.rotate_right(1);
+            history[0] = sample as f64;
+
+            let mut peak = (sample as f64).abs();
+            for phase in &phases {
+                let value: f64 = phase.iter().zip(history.iter()).map(|(h, x)| h * x).sum();
+                peak = peak.max(value.abs());
+            }
+
+            // the interpolation filter delays its output by half its length
+            let delayed = frame_index.saturating_sub(TAPS_PER_PHASE / 2);
+            envelope[delayed] = envelope[delayed].max(peak);
+            envelope[frame_index] = envelope[frame_index].max((sample as f64).abs());
+        }
+    }
+
+    envelope
+}
+
+/// Measures the true peak in dBTP.
+pub fn true_peak(audio: &DecodedAudio) -> f64 {
+    let peak = true_peak_envelope(audio).into_iter().fold(0.0, f64::max);
+    gain_to_db(peak)
+}
+
+/// Applies a static gain followed by a look-ahead limiter keeping the true peak below `ceiling` dBTP.
+pub fn apply_gain_limited(audio: &mut DecodedAudio, gain_db: f64, ceiling: f64) {
+    let gain = db_to_gain(gain_db) as f32;
+    for sample in audio.samples.iter_mut() {
+        *sample *= gain;
+    }
+
+    let ceiling = db_to_gain(ceiling);
+    let required: Vec<f64> = true_peak_envelope(audio)
+        .into_iter()
+        .map(|peak| if peak > ceiling { ceiling / peak } else { 1.0 })
+        .collect();
+    if required.iter().all(|&gain| gain >= 1.0) {
+        return;
+    }
+
+    // ramp the gain down linearly ahead of every peak, then let it recover slowly
+    let lookahead = (LIMITER_LOOKAHEAD_SECS * audio.sample_rate as f64).max(1.0);
+    let attack_step = 1.0 / lookahead;
+    let mut envelope = required;
+    for i in (0..envelope.len().saturating_sub(1)).rev() {
+        envelope[i] = envelope[i].min(envelope[i + 1] + attack_step);
+    }
+    let release = 1.0 - (-1.0 / (LIMITER_RELEASE_SECS * audio.sample_rate as f64)).exp();
+    let mut current = 1.0;
+    for gain in envelope.iter_mut() {
+        current = (current + (1.0 - current) * release).min(*gain);
+        *gain = current;
+    }
+
+    for (frame, gain) in audio.samples.chunks_exact_mut(audio.channels).zip(envelope) {
+        for sample in frame {
+            *sample *= gain as f32;
+        }
+    }
+}
+
+/// Normalizes the integrated loudness to `target` LUFS without exceeding `true_peak` dBTP.
+///
+/// Returns the measured loudness before normalization, silence is left untouched.
+pub fn normalize(audio: &mut DecodedAudio, target: f64, true_peak: f64) -> Option<f64> {
+    let loudness = integrated_loudness(audio)?;
+    apply_gain_limited(audio, target - loudness, true_peak);
+    Some(loudness)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_tone_loudness() {
+        // a 997Hz stereo sine at -23dBFS peak measures -23 LUFS per EBU Tech 3341
+        let audio = DecodedAudio::sine(997.0, db_to_gain(-23.0), 10.0, 2);
+        let loudness = integrated_loudness(&audio).expect("no loudness measured");
+        assert!((loudness + 23.0).abs() < 0.1, "measured {} LUFS", loudness);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let audio = DecodedAudio::sine(997.0, 0.0, 2.0, 1);
+        assert!(integrated_loudness(&audio).is_none());
+    }
+
+    #[test]
+    fn normalize_quiet_tone() {
+        let mut audio = DecodedAudio::sine(440.0, 0.01, 5.0, 1);
+        normalize(&mut audio, -16.0, -1.0).expect("no loudness measured");
+        let loudness = integrated_loudness(&audio).expect("no loudness measured");
+        assert!((loudness + 16.0).abs() < 0.5, "measured {} LUFS", loudness);
+        assert!(true_peak(&audio) <= -0.9, "true peak {}", true_peak(&audio));
+    }
+
+    #[test]
+    fn limit_loud_tone() {
+        let mut audio = DecodedAudio::sine(3000.0, 0.5, 5.0, 2);
+        normalize(&mut audio, -6.0, -1.0).expect("no loudness measured");
+        assert!(true_peak(&audio) <= -0.9, "true peak {}", true_peak(&audio));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{fmt, fs::File, path::Path, time::Duration};
+
+use symphonia::core::{
+    codecs::{CodecType, CODEC_TYPE_NULL, CODEC_TYPE_OPUS},
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use super::{decode::DecodeAudioError, format_duration};
+
+/// What symphonia found out about a file without decoding it.
+pub struct AudioInfo {
+    pub codec: String,
+    pub duration: Option<Duration>,
+}
+
+impl fmt::Display for AudioInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.duration {
+            Some(duration) => write!(f, "{}, {}", self.codec, format_duration(duration)),
+            None => write!(f, "{}", self.codec),
+        }
+    }
+}
+
+fn codec_name(codec: CodecType) -> String {
+    // opus can be demuxed but isn't registered, as symphonia lacks a decoder for it
+    if codec == CODEC_TYPE_OPUS {
+        return "opus".into();
+    }
+    match symphonia::default::get_codecs().get_codec(codec) {
+        Some(descriptor) => descriptor.short_name.into(),
+        None => format!("codec {}", codec),
+    }
+}
+
+/// Detects the container and the codec of the first audio track and reads its duration.
+pub fn probe_file(path: &Path) -> Result<AudioInfo, DecodeAudioError> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let params = &probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(DecodeAudioError::NoTrack)?
+        .codec_params;
+
+    let duration = match (params.n_frames, params.time_base, params.sample_rate) {
+        (Some(n_frames), Some(time_base), _) => {
+            let time = time_base.calc_time(n_frames);
+            Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+        }
+        (Some(n_frames), None, Some(sample_rate)) => Some(Duration::from_secs_f64(
+            n_frames as f64 / sample_rate as f64,
+        )),
+        _ => None,
+    };
+
+    Ok(AudioInfo {
+        codec: codec_name(params.codec),
+        duration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::audio::{decode::DecodedAudio, wav::write_wav};
+
+    #[test]
+    fn probe_wav() {
+        let path = env::temp_dir().join("tg-voice-relay-probe.wav");
+        write_wav(&path, &DecodedAudio::sine(440.0, 0.5, 1.5, 2)).expect("failed to write wav");
+        let info = probe_file(&path).expect("failed to probe wav");
+        fs::remove_file(&path).expect("failed to remove wav");
+
+        assert_eq!(info.codec, "pcm_s16le");
+        assert_eq!(info.to_string(), "pcm_s16le, 0:02");
+        let duration = info.duration.expect("no duration").as_secs_f64();
+        assert!((duration - 1.5).abs() < 0.01, "duration {}s", duration);
+    }
+
+    #[test]
+    fn reject_document() {
+        let path = env::temp_dir().join("tg-voice-relay-probe.pdf");
+        fs::write(&path, "%PDF-1.4\n%\u{e2}\u{e3}\u{cf}\u{d3}\n1 0 obj\n")
+            .expect("failed to write pdf");
+        let res = probe_file(&path);
+        fs::remove_file(&path).expect("failed to remove pdf");
+
+        assert!(matches!(res, Err(DecodeAudioError::Unsupported)));
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::{fs, process::Command, sync, task};
+
+use super::{
+    decode::{decode_file, DecodeAudioError, DecodedAudio},
+    dsp::DspProfiles,
+    loudness, silence, wav,
+};
+use crate::config::AppConfig;
+
+#[derive(Error, Debug)]
+pub enum ProcessAudioError {
+    #[error("failed to decode audio")]
+    Decode(#[from] DecodeAudioError),
+    #[error("failed to write processed audio")]
+    Write(#[from] hound::Error),
+    #[error("io error")]
+    Io(#[from] io::Error),
+    #[error("decoder command parse error")]
+    DecoderCommandParse,
+    #[error("processing task panicked")]
+    Join(#[from] task::JoinError),
+}
+
+pub struct AudioProcessorConfig {
+    pub loudness_target: Option<f64>,
+    pub loudness_true_peak: f64,
+    pub decoder_command: Option<String>,
+    pub dsp_profiles: DspProfiles,
+    pub silence_threshold: Option<f64>,
+    pub silence_padding: u64,
+}
+
+impl From<&AppConfig> for AudioProcessorConfig {
+    fn from(app_config: &AppConfig) -> Self {
+        AudioProcessorConfig {
+            loudness_target: app_config.env.loudness_target,
+            loudness_true_peak: app_config.env.loudness_true_peak,
+            decoder_command: app_config.env.decoder_command.to_owned(),
+            dsp_profiles: app_config.dsp_profiles.clone(),
+            silence_threshold: app_config.env.silence_threshold,
+            silence_padding: app_config.env.silence_padding,
+        }
+    }
+}
+
+pub struct AudioProcessor {
+    loudness_target: Option<f64>,
+    loudness_true_peak: f64,
+    decoder_command: Option<String>,
+    dsp_profiles: DspProfiles,
+    silence_threshold: Option<f64>,
+    silence_padding: u64,
+    /// Locks per output file, so a prefetch and a playback don't process the same audio twice.
+    jobs: Mutex<HashMap<PathBuf, Arc<sync::Mutex<()>>>>,
+    /// Audios being prefetched, so offering one again doesn't queue it twice.
+    prefetching: Mutex<HashSet<PathBuf>>,
+}
+
+/// The file to play, with its duration if it was decoded during processing.
+pub struct ProcessedAudio {
+    pub path: PathBuf,
+    pub duration: Option<Duration>,
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.{}", stem, suffix))
+}
+
+impl AudioProcessor {
+    pub fn new(config: &AudioProcessorConfig) -> Self {
+        let processing = config.loudness_target.is_some()
+            || config.silence_threshold.is_some()
+            || !config.dsp_profiles.profiles.is_empty();
+        if processing && config.decoder_command.is_none() {
+            log::warn!("DECODER_COMMAND is not set, voice messages in opus will play unprocessed");
+        }
+        AudioProcessor {
+            loudness_target: config.loudness_target,
+            loudness_true_peak: config.loudness_true_peak,
+            decoder_command: config.decoder_command.clone(),
+            dsp_profiles: config.dsp_profiles.clone(),
+            silence_threshold: config.silence_threshold,
+            silence_padding: config.silence_padding,
+            jobs: Mutex::new(HashMap::new()),
+            prefetching: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn job_lock(&self, dst_path: &Path) -> Arc<sync::Mutex<()>> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        jobs.retain(|_, lock| Arc::strong_count(lock) > 1);
+        jobs.entry(dst_path.to_owned()).or_default().clone()
+    }
+
+    /// Processes the audio for all rooms in the background, so playback can start right away.
+    /// Rooms processed alike are done once, and an audio already being prefetched is skipped.
+    pub fn prefetch(self: &Arc<Self>, path: PathBuf, room_names: Vec<String>) {
+        let mut suffixes = HashSet::new();
+        let room_names: Vec<String> = room_names
+            .into_iter()
+            .filter(|room_name| {
+                self.processed_suffix(room_name)
+                    .is_some_and(|suffix| suffixes.insert(suffix))
+            })
+            .collect();
+        if room_names.is_empty() {
+            return;
+        }
+        if !self.prefetching().insert(path.clone()) {
+            return;
+        }
+
+        let processor = self.clone();
+        tokio::spawn(async move {
+            for room_name in room_names {
+                if let Err(err) = processor.process(&path, &room_name).await {
+                    log::warn!("failed to prefetch audio for {}: {}", room_name, err);
+                }
+            }
+            processor.prefetching().remove(&path);
+        });
+    }
+
+    fn prefetching(&self) -> std::sync::MutexGuard<'_, HashSet<PathBuf>> {
+        self.prefetching
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Decodes the file with symphonia, falling back to the decoder command for codecs it lacks (i. e. opus).
+    pub async fn decode(&self, path: &Path) -> Result<DecodedAudio, ProcessAudioError> {
+        let owned_path = path.to_owned();
+        let res = task::spawn_blocking(move || decode_file(&owned_path)).await?;
+        let decoder_command = match (res, &self.decoder_command) {
+            (Err(DecodeAudioError::Unsupported), Some(decoder_command)) => decoder_command,
+            (res, _) => return Ok(res?),
+        };
+
+        let wav_path = sibling_path(path, "decoded.wav");
+        let mut args = shell_words::split(decoder_command)
+            .map_err(|_| ProcessAudioError::DecoderCommandParse)?
+            .into_iter()
+            .map(|arg| match arg.as_str() {
+                "%f" => path.to_string_lossy().into_owned(),
+                "%o" => wav_path.to_string_lossy().into_owned(),
+                _ => arg,
+            });
+        let shell = args.next().ok_or(ProcessAudioError::DecoderCommandParse)?;
+        let output = Command::new(shell).args(args).output().await?;
+        if !output.status.success() {
+            if let Ok(stderr_str) = std::str::from_utf8(&output.stderr) {
+                log::warn!("decoder stderr: {}", stderr_str);
+            }
+            return Err(io::Error::other("decoder exited unsuccessfully").into());
+        }
+
+        let res = task::spawn_blocking(move || {
+            let res = decode_file(&wav_path);
+            let _ = std::fs::remove_file(&wav_path);
+            res
+        })
+        .await?;
+        Ok(res?)
+    }
+
+    /// Names the processed copy for the room after all settings, so edited profiles don't replay
+    /// stale results and rooms processed alike share a copy. `None` if the audio plays as it is.
+    fn processed_suffix(&self, room_name: &str) -> Option<String> {
+        let profile = self
+            .dsp_profiles
+            .for_room(room_name)
+            .map(|(_, profile)| profile);
+        if profile.is_none() && self.loudness_target.is_none() && self.silence_threshold.is_none() {
+            return None;
+        }
+        // sha2, as the std hasher may change between Rust releases
+        let option_bits = |value: Option<f64>| value.map_or([0xff; 8], f64::to_le_bytes);
+        let hash = Sha256::new()
+            .chain_update(serde_json::to_string(&profile).unwrap_or_default())
+            .chain_update(b"\0")
+            .chain_update(option_bits(self.loudness_target))
+            .chain_update(self.loudness_true_peak.to_le_bytes())
+            .chain_update(option_bits(self.silence_threshold))
+            .chain_update(self.silence_padding.to_le_bytes())
+            .finalize();
+        Some(format!("processed-{}.wav", &format!("{:x}", hash)[..16]))
+    }
+
+    /// Returns a copy processed for the room, which is cached next to the original.
+    pub async fn process(
+        &self,
+        path: &Path,
+        room_name: &str,
+    ) -> Result<ProcessedAudio, ProcessAudioError> {
+        let Some(suffix) = self.processed_suffix(room_name) else {
+            return Ok(ProcessedAudio {
+                path: path.to_owned(),
+                duration: None,
+            });
+        };
+        let profile = self
+            .dsp_profiles
+            .for_room(room_name)
+            .map(|(name, profile)| (name.to_owned(), profile.clone()));
+
+        let dst_path = sibling_path(path, &suffix);
+        let job_lock = self.job_lock(&dst_path);
+        let _job_guard = job_lock.lock().await;
+        if fs::try_exists(&dst_path).await.unwrap_or(false) {
+            let duration = hound::WavReader::open(&dst_path).ok().map(|reader| {
+                Duration::from_secs_f64(reader.duration() as f64 / reader.spec().sample_rate as f64)
+            });
+            return Ok(ProcessedAudio {
+                path: dst_path,
+                duration,
+            });
+        }
+
+        let mut audio = self.decode(path).await?;
+        let loudness_target = self.loudness_target;
+        let true_peak = self.loudness_true_peak;
+        let silence_threshold = self.silence_threshold;
+        let silence_padding = Duration::from_millis(self.silence_padding).as_secs_f64();
+        let tmp_path = sibling_path(path, &format!("{}.part", suffix));
+        let write_path = tmp_path.clone();
+        let duration = task::spawn_blocking(move || {
+            if let Some(silence_threshold) = silence_threshold {
+                let (leading, trailing) =
+                    silence::trim_silence(&mut audio, silence_threshold, silence_padding);
+                log::info!(
+                    "trimmed {:.1}s of leading and {:.1}s of trailing silence",
+                    leading,
+                    trailing
+                );
+            }
+            if let Some((name, profile)) = profile {
+                profile.apply(&mut audio);
+                log::info!("applied dsp profile {}", name);
+            }
+            if let Some(loudness_target) = loudness_target {
+                match loudness::normalize(&mut audio, loudness_target, true_peak) {
+                    Some(loudness) => log::info!(
+                        "normalized audio from {:.1} LUFS to {:.1} LUFS, true peak at {:.1} dBTP",
+                        loudness,
+                        loudness_target,
+                        loudness::true_peak(&audio)
+                    ),
+                    None => log::info!("audio is silent, skipping loudness normalization"),
+                }
+            }
+            wav::write_wav(&write_path, &audio)?;
+            hound::Result::Ok(Duration::from_secs_f64(
+                audio.frames() as f64 / audio.sample_rate as f64,
+            ))
+        })
+        .await??;
+        fs::rename(&tmp_path, &dst_path).await?;
+
+        Ok(ProcessedAudio {
+            path: dst_path,
+            duration: Some(duration),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rooms_processed_alike_share_a_copy() {
+        let dsp_profiles = serde_json::from_str(
+            r#"{
+                "rooms": {"Hall": "voice", "Lobby": "speech", "Gym": "loud"},
+                "profiles": {
+                    "voice": {"high_pass": {"frequency": 120.0}},
+                    "speech": {"high_pass": {"frequency": 120.0}},
+                    "loud": {"compressor": {}}
+                }
+            }"#,
+        )
+        .unwrap();
+        let processor = AudioProcessor::new(&AudioProcessorConfig {
+            loudness_target: None,
+            loudness_true_peak: -1.0,
+            decoder_command: None,
+            dsp_profiles,
+            silence_threshold: None,
+            silence_padding: 150,
+        });
+
+        let hall = processor.processed_suffix("Hall");
+        assert!(hall.is_some());
+        assert_eq!(hall, processor.processed_suffix("Lobby"));
+        assert_ne!(hall, processor.processed_suffix("Gym"));
+        // rooms without a profile play the audio as it is
+        assert_eq!(processor.processed_suffix("Yard"), None);
+    }
+}
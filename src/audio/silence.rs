@@ -0,0 +1,109 @@
+use super::{decode::DecodedAudio, dsp::db_to_gain};
+
+const WINDOW_SECS: f64 = 0.01;
+
+/// Finds the first and last 10ms windows louder than the threshold in dBFS and cuts
+/// everything outside of them, keeping `padding_secs` of the surroundings.
+///
+/// Returns the number of seconds removed from the start and the end.
+pub fn trim_silence(audio: &mut DecodedAudio, threshold_db: f64, padding_secs: f64) -> (f64, f64) {
+    let channels = audio.channels;
+    let window = ((WINDOW_SECS * audio.sample_rate as f64) as usize).max(1);
+    let threshold = db_to_gain(threshold_db).powi(2);
+
+    let is_loud = |frames: &[f32]| {
+        let power = frames.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / frames.len() as f64;
+        power > threshold
+    };
+    let windows: Vec<bool> = audio
+        .samples
+        .chunks(window * channels)
+        .map(is_loud)
+        .collect();
+
+    let frames = audio.frames();
+    let (first, last) = match (
+        windows.iter().position(|&loud| loud),
+        windows.iter().rposition(|&loud| loud),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        // nothing but silence, better play it as it is than play nothing
+        _ => return (0.0, 0.0),
+    };
+
+    let padding = (padding_secs * audio.sample_rate as f64) as usize;
+    let start = (first * window).saturating_sub(padding);
+    let end = ((last + 1) * window + padding).min(frames);
+
+    audio.samples.truncate(end * channels);
+    audio.samples.drain(..start * channels);
+
+    let rate = audio.sample_rate as f64;
+    (start as f64 / rate, (frames - end) as f64 / rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_silence(leading_secs: f64, speech_secs: f64, trailing_secs: f64) -> DecodedAudio {
+        let silence = |secs| DecodedAudio::sine(0.0, 0.0, secs, 2).samples;
+        let speech = DecodedAudio::sine(440.0, 0.2, speech_secs, 2);
+        DecodedAudio {
+            samples: [
+                silence(leading_secs),
+                speech.samples.clone(),
+                silence(trailing_secs),
+            ]
+            .concat(),
+            ..speech
+        }
+    }
+
+    #[test]
+    fn trims_both_ends() {
+        let mut audio = with_silence(2.0, 3.0, 1.5);
+        let (leading, trailing) = trim_silence(&mut audio, -50.0, 0.1);
+        assert!(
+            (leading - 1.9).abs() < 0.02,
+            "trimmed {}s at the start",
+            leading
+        );
+        assert!(
+            (trailing - 1.4).abs() < 0.02,
+            "trimmed {}s at the end",
+            trailing
+        );
+        let duration = audio.frames() as f64 / audio.sample_rate as f64;
+        assert!((duration - 3.2).abs() < 0.03, "{}s left", duration);
+    }
+
+    #[test]
+    fn keeps_audio_without_silence() {
+        let mut audio = with_silence(0.0, 2.0, 0.0);
+        let frames = audio.frames();
+        assert_eq!(trim_silence(&mut audio, -50.0, 0.1), (0.0, 0.0));
+        assert_eq!(audio.frames(), frames);
+    }
+
+    #[test]
+    fn keeps_complete_silence() {
+        let mut audio = with_silence(1.0, 0.0, 1.0);
+        let frames = audio.frames();
+        assert_eq!(trim_silence(&mut audio, -50.0, 0.1), (0.0, 0.0));
+        assert_eq!(audio.frames(), frames);
+    }
+
+    #[test]
+    fn quiet_noise_counts_as_silence() {
+        let noise = DecodedAudio::sine(3000.0, db_to_gain(-60.0), 1.0, 2);
+        let mut audio = with_silence(0.0, 1.0, 0.0);
+        audio.samples = [noise.samples.clone(), audio.samples].concat();
+        let (leading, _) = trim_silence(&mut audio, -40.0, 0.0);
+        assert!(
+            (leading - 1.0).abs() < 0.02,
+            "trimmed {}s at the start",
+            leading
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use std::path::Path;
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use super::decode::DecodedAudio;
+
+/// Writes the audio as 16 bit PCM, which every player command is able to read.
+pub fn write_wav(path: &Path, audio: &DecodedAudio) -> hound::Result<()> {
+    let spec = WavSpec {
+        channels: audio.channels as u16,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for sample in &audio.samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()
+}
@@ -0,0 +1,330 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+use teloxide::{net::Download, requests::Requester, ApiError, Bot, DownloadError, RequestError};
+use thiserror::Error;
+use tokio::fs::{self, File};
+
+use crate::backoff::{RetryPolicy, Transient};
+
+/// Bots may only download files up to 20 MB through the Bot API.
+pub const TELEGRAM_DOWNLOAD_LIMIT: u32 = 20 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum DownloadAudioError {
+    #[error("the file exceeds the download limit of the Bot API")]
+    TooBig,
+    #[error("failed to get file info: {0}")]
+    Request(#[from] RequestError),
+    #[error("failed to download file: {0}")]
+    Download(#[from] DownloadError),
+    #[error("failed to write file: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to update audio cache: {0}")]
+    Db(#[from] sqlx::Error),
+}
+
+impl Transient for DownloadAudioError {
+    fn is_transient(&self) -> bool {
+        match self {
+            DownloadAudioError::Request(err) => err.is_transient(),
+            DownloadAudioError::Download(_) | DownloadAudioError::Io(_) => true,
+            DownloadAudioError::TooBig | DownloadAudioError::Db(_) => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            DownloadAudioError::Request(err) => err.retry_after(),
+            _ => None,
+        }
+    }
+}
+
+const DOWNLOAD_RETRY: RetryPolicy = RetryPolicy {
+    initial_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(8),
+    max_attempts: Some(5),
+    max_elapsed: Some(Duration::from_secs(60)),
+    jitter: true,
+};
+
+/// A downloaded telegram file, stored under its `file_unique_id` so forwarded copies share it.
+/// Synthesized speech has no telegram file and an empty `file_id`.
+pub struct AudioCacheEntry {
+    pub unique_id: String,
+    pub file_id: String,
+    pub file_name: String,
+    pub size: i64,
+    pub duration_ms: Option<i64>,
+    pub checksum: String,
+}
+
+/// A file next to `file_name` to write to before moving it into place. Every call returns another
+/// one, so concurrent downloads of the same audio don't write into the same file.
+pub fn temp_path(audio_dir: &Path, file_name: &str) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    audio_dir.join(format!("{}.{}.part", file_name, n))
+}
+
+pub async fn checksum(path: &Path) -> io::Result<String> {
+    let content = fs::read(path).await?;
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+impl AudioCacheEntry {
+    pub fn path(&self, audio_dir: &Path) -> PathBuf {
+        audio_dir.join(&self.file_name)
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_ms
+            .map(|duration_ms| Duration::from_millis(duration_ms as u64))
+    }
+
+    pub async fn find(db: &Pool<Sqlite>, unique_id: &str) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as!(
+            AudioCacheEntry,
+            "SELECT unique_id, file_id, file_name, size, duration_ms, checksum
+                FROM audio_cache WHERE unique_id = ?",
+            unique_id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    pub async fn set_duration(
+        &mut self,
+        db: &Pool<Sqlite>,
+        duration: Duration,
+    ) -> sqlx::Result<()> {
+        let duration_ms = duration.as_millis() as i64;
+        sqlx::query!(
+            "UPDATE audio_cache SET duration_ms = ? WHERE unique_id = ?",
+            duration_ms,
+            self.unique_id
+        )
+        .execute(db)
+        .await?;
+        self.duration_ms = Some(duration_ms);
+        Ok(())
+    }
+
+    /// Checks that the file is still there with the size it was downloaded with, which is cheap
+    /// enough to do before every play.
+    pub async fn is_present(&self, audio_dir: &Path) -> bool {
+        matches!(
+            fs::metadata(self.path(audio_dir)).await,
+            Ok(metadata) if metadata.len() == self.size as u64
+        )
+    }
+
+    /// Checks that the file wasn't truncated or altered since the download, reading all of it.
+    pub async fn verify(&self, audio_dir: &Path) -> bool {
+        self.is_present(audio_dir).await
+            && matches!(checksum(&self.path(audio_dir)).await, Ok(checksum) if checksum == self.checksum)
+    }
+
+    /// Entries which may be deleted by the retention policy, least recently used first.
+    /// Saved clips and scheduled audios are kept.
+    pub async fn evictable(db: &Pool<Sqlite>) -> sqlx::Result<Vec<(Self, i64)>> {
+        let rows = sqlx::query!(
+            "SELECT unique_id, file_id, file_name, size, duration_ms, checksum, last_used_at
+                FROM audio_cache WHERE NOT EXISTS (
+                    SELECT 1 FROM clips WHERE clips.unique_id = audio_cache.unique_id
+                ) AND NOT EXISTS (
+                    SELECT 1 FROM schedules WHERE schedules.unique_id = audio_cache.unique_id
+                )
+                ORDER BY last_used_at"
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let entry = AudioCacheEntry {
+                    unique_id: row.unique_id,
+                    file_id: row.file_id,
+                    file_name: row.file_name,
+                    size: row.size,
+                    duration_ms: row.duration_ms,
+                    checksum: row.checksum,
+                };
+                (entry, row.last_used_at)
+            })
+            .collect())
+    }
+
+    /// Records a file which was just stored in the audio dir, replacing an older entry.
+    pub async fn insert(&self, db: &Pool<Sqlite>) -> sqlx::Result<()> {
+        sqlx::query!(
+            "INSERT INTO audio_cache (unique_id, file_id, file_name, size, checksum, last_used_at)
+                VALUES ($1, $2, $3, $4, $5, unixepoch())
+                ON CONFLICT(unique_id) DO UPDATE
+                SET file_id = $2, file_name = $3, size = $4, duration_ms = NULL, checksum = $5,
+                    last_used_at = unixepoch()",
+            self.unique_id,
+            self.file_id,
+            self.file_name,
+            self.size,
+            self.checksum
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn touch(&self, db: &Pool<Sqlite>) -> sqlx::Result<()> {
+        sqlx::query!(
+            "UPDATE audio_cache SET last_used_at = unixepoch() WHERE unique_id = ?",
+            self.unique_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Remembers who sent the audio, so it can be deleted on their request.
+    pub async fn record_upload(&self, db: &Pool<Sqlite>, user_id: i64) -> sqlx::Result<()> {
+        sqlx::query!(
+            "INSERT INTO audio_uploads (unique_id, user_id) VALUES (?, ?)
+                ON CONFLICT(unique_id, user_id) DO UPDATE SET created_at = unixepoch()",
+            self.unique_id,
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Whether a file in the audio dir is this audio or was derived from it.
+    pub fn owns_file(&self, file_name: &str) -> bool {
+        file_name
+            .strip_prefix(&self.unique_id)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    }
+
+    /// Deletes the audio with all processed copies, returning the number of bytes freed.
+    pub async fn remove(&self, db: &Pool<Sqlite>, audio_dir: &Path) -> sqlx::Result<u64> {
+        let mut freed = 0;
+        match fs::read_dir(audio_dir).await {
+            Ok(mut dir) => {
+                while let Ok(Some(dir_entry)) = dir.next_entry().await {
+                    let file_name = dir_entry.file_name();
+                    if !self.owns_file(&file_name.to_string_lossy()) {
+                        continue;
+                    }
+                    let size = dir_entry.metadata().await.map(|m| m.len()).unwrap_or(0);
+                    match fs::remove_file(dir_entry.path()).await {
+                        Ok(()) => freed += size,
+                        Err(err) => {
+                            log::error!("failed to remove cached audio {:?}: {}", file_name, err)
+                        }
+                    }
+                }
+            }
+            Err(err) => log::error!("failed to read audio dir: {}", err),
+        }
+        sqlx::query!(
+            "DELETE FROM audio_uploads WHERE unique_id = ?",
+            self.unique_id
+        )
+        .execute(db)
+        .await?;
+        sqlx::query!("DELETE FROM speech WHERE unique_id = ?", self.unique_id)
+            .execute(db)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM audio_cache WHERE unique_id = ?",
+            self.unique_id
+        )
+        .execute(db)
+        .await?;
+        Ok(freed)
+    }
+}
+
+/// Returns the cached copy of a telegram file, downloading it again if it is missing or truncated.
+/// Files of a `file_size` above the download limit are rejected without asking Telegram.
+pub async fn fetch_audio(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    audio_dir: &Path,
+    file_id: &str,
+    unique_id: &str,
+    file_size: Option<u64>,
+) -> Result<AudioCacheEntry, DownloadAudioError> {
+    if let Some(entry) = AudioCacheEntry::find(db, unique_id).await? {
+        if entry.is_present(audio_dir).await {
+            entry.touch(db).await?;
+            return Ok(entry);
+        }
+        log::warn!(
+            "cached audio {} is missing or truncated, downloading it again",
+            entry.file_name
+        );
+        entry.remove(db, audio_dir).await?;
+    }
+
+    if file_size.is_some_and(|file_size| file_size > TELEGRAM_DOWNLOAD_LIMIT as u64) {
+        return Err(DownloadAudioError::TooBig);
+    }
+    let file = DOWNLOAD_RETRY
+        .retry("getting file info", || async {
+            match bot.get_file(file_id).await {
+                Ok(file) => Ok(file),
+                // in case the reported size was missing or off, the API has no error code for it
+                Err(RequestError::Api(ApiError::Unknown(text)))
+                    if text.contains("file is too big") =>
+                {
+                    Err(DownloadAudioError::TooBig)
+                }
+                Err(err) => Err(err.into()),
+            }
+        })
+        .await?;
+
+    // keep the extension, as it helps detecting the format later on
+    let file_name = match Path::new(&file.path).extension() {
+        Some(ext) => format!("{}.{}", unique_id, ext.to_string_lossy()),
+        None => unique_id.to_owned(),
+    };
+    let dst_path = audio_dir.join(&file_name);
+    let tmp_path = temp_path(audio_dir, &file_name);
+
+    // a failed attempt leaves a partial download behind, which the next one overwrites
+    let downloaded = DOWNLOAD_RETRY
+        .retry("downloading file", || async {
+            let mut dst = File::create(&tmp_path).await?;
+            bot.download_file(&file.path, &mut dst).await?;
+            dst.sync_all().await?;
+            Result::<(), DownloadAudioError>::Ok(())
+        })
+        .await;
+    if let Err(err) = downloaded {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(err);
+    }
+
+    let size = fs::metadata(&tmp_path).await?.len() as i64;
+    let checksum = checksum(&tmp_path).await?;
+    fs::rename(&tmp_path, &dst_path).await?;
+
+    let entry = AudioCacheEntry {
+        unique_id: unique_id.to_owned(),
+        file_id: file_id.to_owned(),
+        file_name,
+        size,
+        duration_ms: None,
+        checksum,
+    };
+    entry.insert(db).await?;
+    Ok(entry)
+}
@@ -1,40 +1,246 @@
-pub enum BackoffVariant {
-    Exponential,
+use std::{fmt::Display, future::Future, io, time::Duration};
+
+use rand::Rng;
+use teloxide::{DownloadError, RequestError};
+use tokio::time::{self, Instant};
+
+/// When and how often a failed operation is attempted again.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Delay after the first failure, doubled after every further one.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Total number of attempts, including the first one.
+    pub max_attempts: Option<u32>,
+    /// Gives up once this much time passed since the first attempt.
+    pub max_elapsed: Option<Duration>,
+    /// Picks a random delay between half and the full delay, so clients don't retry in lockstep.
+    pub jitter: bool,
 }
 
-impl BackoffVariant {
-    fn get_multiplier(&self, tries: u64) -> u64 {
-        return match self {
-            Self::Exponential => tries.pow(2),
-        };
+impl RetryPolicy {
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            policy: self.clone(),
+            attempts: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Runs the operation until it succeeds, fails permanently or the policy is exhausted.
+    pub async fn retry<T, E, F, Fut>(&self, action: &str, mut operation: F) -> Result<T, E>
+    where
+        E: Transient + Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut backoff = self.backoff();
+        loop {
+            let err = match operation().await {
+                Ok(res) => return Ok(res),
+                Err(err) if !err.is_transient() => return Err(err),
+                Err(err) => err,
+            };
+            let delay = match (backoff.next(), err.retry_after()) {
+                (None, _) => {
+                    log::error!("{} failed, giving up: {}", action, err);
+                    return Err(err);
+                }
+                (Some(delay), Some(retry_after)) => delay.max(retry_after),
+                (Some(delay), None) => delay,
+            };
+            log::warn!(
+                "{} failed, retrying in {:.1}s: {}",
+                action,
+                delay.as_secs_f64(),
+                err
+            );
+            time::sleep(delay).await;
+        }
     }
 }
 
+/// Exponentially growing delays between attempts of a [`RetryPolicy`].
 pub struct Backoff {
-    variant: BackoffVariant,
-    tries: u64,
-    max: Option<u64>,
+    policy: RetryPolicy,
+    attempts: u32,
+    started: Instant,
 }
 
 impl Backoff {
-    pub fn new(variant: BackoffVariant, max: Option<u64>) -> Self {
-        Backoff {
-            variant,
-            tries: 0,
-            max,
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.started = Instant::now();
+    }
+
+    /// The delay before the next attempt, or `None` if the policy doesn't allow another one.
+    pub fn next(&mut self) -> Option<Duration> {
+        self.attempts += 1;
+        if let Some(max_attempts) = self.policy.max_attempts {
+            if self.attempts >= max_attempts {
+                return None;
+            }
+        }
+
+        let factor = 2u32.saturating_pow(self.attempts - 1);
+        let mut delay = self
+            .policy
+            .initial_delay
+            .saturating_mul(factor)
+            .min(self.policy.max_delay);
+        if self.policy.jitter {
+            delay = rand::thread_rng().gen_range(delay / 2..=delay);
+        }
+
+        if let Some(max_elapsed) = self.policy.max_elapsed {
+            if self.started.elapsed() + delay > max_elapsed {
+                return None;
+            }
         }
+        Some(delay)
     }
+}
 
-    pub fn reset(&mut self) {
-        self.tries = 0;
+/// Errors which may go away when the operation is attempted again.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+
+    /// How long the other side asked us to wait.
+    fn retry_after(&self) -> Option<Duration> {
+        None
     }
+}
 
-    pub fn next(&mut self) -> u64 {
-        let mut val = self.variant.get_multiplier(self.tries);
-        if let Some(max) = self.max {
-            val = std::cmp::min(val, max);
+impl Transient for io::Error {
+    fn is_transient(&self) -> bool {
+        true
+    }
+}
+
+impl Transient for RequestError {
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            RequestError::Network(_) | RequestError::Io(_) | RequestError::RetryAfter(_)
+        )
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RequestError::RetryAfter(seconds) => Some(seconds.duration()),
+            _ => None,
         }
-        self.tries += 1;
-        val
+    }
+}
+
+impl Transient for DownloadError {
+    fn is_transient(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(10),
+        max_attempts: None,
+        max_elapsed: None,
+        jitter: false,
+    };
+
+    fn failing(attempts: &Cell<u32>, failures: u32) -> impl Future<Output = io::Result<u32>> + '_ {
+        attempts.set(attempts.get() + 1);
+        let attempt = attempts.get();
+        async move {
+            if attempt <= failures {
+                Err(io::Error::other("connection lost"))
+            } else {
+                Ok(attempt)
+            }
+        }
+    }
+
+    #[test]
+    fn grows_exponentially() {
+        let mut backoff = POLICY.backoff();
+        let delays: Vec<u64> = (0..6)
+            .map(|_| backoff.next().expect("no delay").as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        backoff.reset();
+        assert_eq!(backoff.next(), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let mut backoff = RetryPolicy {
+            jitter: true,
+            ..POLICY
+        }
+        .backoff();
+        for expected in [1000, 2000, 4000, 8000] {
+            let delay = backoff.next().expect("no delay").as_millis();
+            assert!(
+                (expected / 2..=expected).contains(&delay),
+                "delay {}ms",
+                delay
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_until_success() {
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let res = POLICY.retry("test", || failing(&attempts, 3)).await;
+        assert_eq!(res.expect("retry failed"), 4);
+        assert_eq!(start.elapsed(), Duration::from_secs(1 + 2 + 4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: Some(3),
+            ..POLICY
+        };
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let res = policy.retry("test", || failing(&attempts, 5)).await;
+        assert!(res.is_err());
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(start.elapsed(), Duration::from_secs(1 + 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_elapsed() {
+        let policy = RetryPolicy {
+            max_elapsed: Some(Duration::from_secs(10)),
+            ..POLICY
+        };
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let res = policy.retry("test", || failing(&attempts, 10)).await;
+        assert!(res.is_err());
+        // waiting another 8s after 1 + 2 + 4 would exceed the limit
+        assert_eq!(attempts.get(), 4);
+        assert_eq!(start.elapsed(), Duration::from_secs(7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_errors_are_not_retried() {
+        let attempts = Cell::new(0);
+        let res: Result<(), RequestError> = POLICY
+            .retry("test", || {
+                attempts.set(attempts.get() + 1);
+                async { Err(RequestError::MigrateToChatId(teloxide::types::ChatId(1))) }
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.get(), 1);
     }
 }
@@ -0,0 +1,436 @@
+use std::io::BufReader;
+
+use chrono::{
+    DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday,
+};
+use chrono_tz::Tz;
+use ical::{
+    parser::{ical::component::IcalEvent, ParserError},
+    property::Property,
+    IcalParser,
+};
+use thiserror::Error;
+
+/// Recurring events are imported for this long, routines suit endless series better.
+const IMPORT_HORIZON: Days = Days::new(90);
+
+/// Why an event of a calendar can't be imported.
+#[derive(Error, Debug, PartialEq)]
+pub enum SkipReason {
+    #[error("no clip, add \"clip: <name>\" to the description")]
+    MissingClip,
+    #[error("no room, add \"room: <name>\" to the description or set the location")]
+    MissingRoom,
+    #[error("unknown clip {0}")]
+    UnknownClip(String),
+    #[error("unknown room {0}")]
+    UnknownRoom(String),
+    #[error("invalid start {0}")]
+    InvalidStart(String),
+    #[error("all-day events have no time to play at")]
+    AllDay,
+    #[error("unsupported recurrence {0}")]
+    UnsupportedRule(String),
+    #[error("no upcoming dates")]
+    NoOccurrences,
+}
+
+/// An event referencing a clip and a room, with its upcoming dates.
+pub struct CalendarEvent {
+    pub summary: String,
+    pub clip_name: String,
+    pub room_name: String,
+    pub occurrences: Vec<DateTime<Tz>>,
+}
+
+pub struct SkippedEvent {
+    pub summary: String,
+    pub reason: SkipReason,
+}
+
+fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+    event.properties.iter().find(|prop| prop.name == name)
+}
+
+fn param<'a>(prop: &'a Property, name: &str) -> Option<&'a str> {
+    prop.params
+        .iter()
+        .flatten()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("\\n", "\n")
+        .replace("\\N", "\n")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+/// Reads `key: value` lines like `clip: Bell` from the description.
+fn description_value(description: &str, key: &str) -> Option<String> {
+    description.lines().find_map(|line| {
+        let (line_key, value) = line.split_once(':')?;
+        (line_key.trim().eq_ignore_ascii_case(key) && !value.trim().is_empty())
+            .then(|| value.trim().to_owned())
+    })
+}
+
+/// Parses a date-time value in the timezone of its `TZID`, UTC for a trailing `Z` or the
+/// given timezone for floating times. Unknown timezone names, i. e. from Outlook, fall back
+/// to the given timezone as well.
+fn parse_date_time(
+    value: &str,
+    tzid: Option<&str>,
+    timezone: Tz,
+) -> Result<(NaiveDateTime, Tz), SkipReason> {
+    let invalid = || SkipReason::InvalidStart(value.to_owned());
+    if let Some(utc) = value.strip_suffix('Z') {
+        let date_time =
+            NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        return Ok((date_time, Tz::UTC));
+    }
+    if NaiveDate::parse_from_str(value, "%Y%m%d").is_ok() {
+        return Err(SkipReason::AllDay);
+    }
+    let date_time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+    let timezone = tzid.and_then(|tzid| tzid.parse().ok()).unwrap_or(timezone);
+    Ok((date_time, timezone))
+}
+
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// The supported subset of RRULE: daily, weekly (optionally by day), monthly and yearly
+/// repetitions with an interval, count or end.
+struct Recurrence {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<DateTime<Tz>>,
+    weekdays: Vec<Weekday>,
+}
+
+impl Recurrence {
+    fn parse(rule: &str, timezone: Tz) -> Result<Recurrence, SkipReason> {
+        let unsupported = || SkipReason::UnsupportedRule(rule.to_owned());
+        let mut recurrence = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            weekdays: Vec::new(),
+        };
+        let mut frequency = None;
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(unsupported)?;
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => (Frequency::Daily, 1),
+                        "WEEKLY" => (Frequency::Weekly, 1),
+                        "MONTHLY" => (Frequency::Monthly, 1),
+                        "YEARLY" => (Frequency::Monthly, 12),
+                        _ => return Err(unsupported()),
+                    })
+                }
+                "INTERVAL" => {
+                    recurrence.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(unsupported)?
+                }
+                "COUNT" => recurrence.count = Some(value.parse().map_err(|_| unsupported())?),
+                "UNTIL" => {
+                    recurrence.until = Some(match NaiveDate::parse_from_str(value, "%Y%m%d") {
+                        Ok(date) => timezone
+                            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+                            .earliest()
+                            .and_then(|at| at.checked_add_days(Days::new(1)))
+                            .ok_or_else(unsupported)?,
+                        Err(_) => {
+                            let (until, until_timezone) = parse_date_time(value, None, timezone)
+                                .map_err(|_| unsupported())?;
+                            until_timezone
+                                .from_local_datetime(&until)
+                                .earliest()
+                                .ok_or_else(unsupported)?
+                                .with_timezone(&timezone)
+                        }
+                    })
+                }
+                "BYDAY" => {
+                    recurrence.weekdays = value
+                        .split(',')
+                        .map(|day| match day {
+                            "MO" => Ok(Weekday::Mon),
+                            "TU" => Ok(Weekday::Tue),
+                            "WE" => Ok(Weekday::Wed),
+                            "TH" => Ok(Weekday::Thu),
+                            "FR" => Ok(Weekday::Fri),
+                            "SA" => Ok(Weekday::Sat),
+                            "SU" => Ok(Weekday::Sun),
+                            _ => Err(unsupported()),
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                "WKST" => {}
+                _ => return Err(unsupported()),
+            }
+        }
+
+        let (frequency, months) = frequency.ok_or_else(unsupported)?;
+        if !recurrence.weekdays.is_empty() && !matches!(frequency, Frequency::Weekly) {
+            return Err(unsupported());
+        }
+        recurrence.frequency = frequency;
+        recurrence.interval *= months;
+        Ok(recurrence)
+    }
+
+    /// The local start times of the series, in order, until `end`.
+    fn dates(&self, start: NaiveDateTime, end: NaiveDateTime) -> Vec<NaiveDateTime> {
+        let mut dates = Vec::new();
+        match self.frequency {
+            Frequency::Daily => {
+                let mut date = Some(start);
+                while let Some(current) = date.filter(|current| *current <= end) {
+                    dates.push(current);
+                    date = current.checked_add_days(Days::new(self.interval.into()));
+                }
+            }
+            Frequency::Weekly => {
+                let weekdays = match self.weekdays.is_empty() {
+                    true => vec![start.weekday()],
+                    false => self.weekdays.clone(),
+                };
+                let days_from_monday = start.weekday().num_days_from_monday().into();
+                let mut monday = start.checked_sub_days(Days::new(days_from_monday));
+                while let Some(week) = monday.filter(|week| *week <= end) {
+                    let mut week_dates: Vec<NaiveDateTime> = weekdays
+                        .iter()
+                        .filter_map(|day| {
+                            week.checked_add_days(Days::new(day.num_days_from_monday().into()))
+                        })
+                        .filter(|date| *date >= start && *date <= end)
+                        .collect();
+                    week_dates.sort();
+                    dates.extend(week_dates);
+                    monday = week.checked_add_days(Days::new(7 * u64::from(self.interval)));
+                }
+            }
+            Frequency::Monthly => {
+                // months without the day, i. e. the 31st, are skipped
+                let mut months = 0;
+                while let Some(date) = start.checked_add_months(Months::new(months)) {
+                    if date > end {
+                        break;
+                    }
+                    if date.day() == start.day() {
+                        dates.push(date);
+                    }
+                    months += self.interval;
+                }
+            }
+        }
+        dates
+    }
+}
+
+/// The upcoming start times of an event after `now`, converted to the timezone of `now`.
+fn occurrences(event: &IcalEvent, now: DateTime<Tz>) -> Result<Vec<DateTime<Tz>>, SkipReason> {
+    let local_timezone = now.timezone();
+    let start =
+        property(event, "DTSTART").ok_or_else(|| SkipReason::InvalidStart("missing".into()))?;
+    let (start_time, timezone) = parse_date_time(
+        start.value.as_deref().unwrap_or_default(),
+        param(start, "TZID"),
+        local_timezone,
+    )?;
+    let end = (now + IMPORT_HORIZON)
+        .with_timezone(&timezone)
+        .naive_local();
+
+    let mut dates = match property(event, "RRULE").and_then(|rule| rule.value.as_deref()) {
+        Some(rule) => {
+            let recurrence = Recurrence::parse(rule, timezone)?;
+            let mut dates = recurrence.dates(start_time, end);
+            if let Some(count) = recurrence.count {
+                dates.truncate(count);
+            }
+            let mut dates: Vec<DateTime<Tz>> = dates
+                .into_iter()
+                .filter_map(|date| timezone.from_local_datetime(&date).earliest())
+                .collect();
+            if let Some(until) = recurrence.until {
+                dates.retain(|date| *date <= until);
+            }
+            dates
+        }
+        None => timezone
+            .from_local_datetime(&start_time)
+            .earliest()
+            .into_iter()
+            .collect(),
+    };
+
+    let excluded: Vec<DateTime<Tz>> = event
+        .properties
+        .iter()
+        .filter(|prop| prop.name == "EXDATE")
+        .flat_map(|prop| {
+            let tzid = param(prop, "TZID");
+            prop.value
+                .iter()
+                .flat_map(|value| value.split(','))
+                .filter_map(move |value| parse_date_time(value, tzid, local_timezone).ok())
+        })
+        .filter_map(|(date, timezone)| timezone.from_local_datetime(&date).earliest())
+        .collect();
+    dates.retain(|date| *date > now && !excluded.contains(date));
+    Ok(dates
+        .into_iter()
+        .map(|date| date.with_timezone(&local_timezone))
+        .collect())
+}
+
+fn parse_event(event: &IcalEvent, now: DateTime<Tz>) -> Result<CalendarEvent, SkipReason> {
+    let text = |name| {
+        property(event, name)
+            .and_then(|prop| prop.value.as_deref())
+            .map(unescape)
+    };
+    let description = text("DESCRIPTION").unwrap_or_default();
+    let clip_name = description_value(&description, "clip").ok_or(SkipReason::MissingClip)?;
+    let room_name = description_value(&description, "room")
+        .or_else(|| text("LOCATION").filter(|location| !location.trim().is_empty()))
+        .ok_or(SkipReason::MissingRoom)?;
+
+    let occurrences = occurrences(event, now)?;
+    if occurrences.is_empty() {
+        return Err(SkipReason::NoOccurrences);
+    }
+    Ok(CalendarEvent {
+        summary: text("SUMMARY").unwrap_or_default(),
+        clip_name,
+        room_name: room_name.trim().to_owned(),
+        occurrences,
+    })
+}
+
+/// Reads the events of an iCalendar file which play after `now`, and the ones which can't
+/// be imported.
+pub fn parse_calendar(
+    text: &str,
+    now: DateTime<Tz>,
+) -> Result<(Vec<CalendarEvent>, Vec<SkippedEvent>), ParserError> {
+    let mut events = Vec::new();
+    let mut skipped = Vec::new();
+    for calendar in IcalParser::new(BufReader::new(text.as_bytes())) {
+        for event in calendar?.events {
+            match parse_event(&event, now) {
+                Ok(event) => events.push(event),
+                // past events are expected in a shared calendar and not worth mentioning
+                Err(SkipReason::NoOccurrences) => {}
+                Err(reason) => skipped.push(SkippedEvent {
+                    summary: property(&event, "SUMMARY")
+                        .and_then(|prop| prop.value.as_deref())
+                        .map(unescape)
+                        .unwrap_or_default(),
+                    reason,
+                }),
+            }
+        }
+    }
+    Ok((events, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+SUMMARY:Lunch\r
+DTSTART;TZID=Europe/Berlin:20240429T123000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=6\r
+EXDATE;TZID=Europe/Berlin:20240503T123000\r
+DESCRIPTION:clip: Gong\\nroom: Hall\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Fire drill\r
+DTSTART:20240502T080000Z\r
+LOCATION:Yard\r
+DESCRIPTION:Clip: Alarm\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20240510\r
+DESCRIPTION:clip: Gong\\nroom: Hall\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Meeting\r
+DTSTART:20240510T100000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn at(day: u32, hour: u32, min: u32) -> DateTime<Tz> {
+        Berlin.with_ymd_and_hms(2024, 5, day, hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_events() {
+        let (events, skipped) = parse_calendar(CALENDAR, at(1, 0, 0)).unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary, "Lunch");
+        assert_eq!(events[0].clip_name, "Gong");
+        assert_eq!(events[0].room_name, "Hall");
+        // 2024-04-29 is in the past and 2024-05-03 is excluded
+        assert_eq!(
+            events[0].occurrences,
+            [at(1, 12, 30), at(6, 12, 30), at(8, 12, 30), at(10, 12, 30)]
+        );
+        assert_eq!(events[1].room_name, "Yard");
+        assert_eq!(events[1].occurrences, [at(2, 10, 0)]);
+
+        let reasons: Vec<_> = skipped.into_iter().map(|event| event.reason).collect();
+        assert_eq!(reasons, [SkipReason::AllDay, SkipReason::MissingClip]);
+    }
+
+    #[test]
+    fn expand_recurrences() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 31)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let end = start.checked_add_days(Days::new(100)).unwrap();
+        let days = |rule: &str| {
+            Recurrence::parse(rule, Tz::UTC)
+                .unwrap()
+                .dates(start, end)
+                .into_iter()
+                .map(|date| date.format("%m-%d").to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(days("FREQ=MONTHLY"), ["01-31", "03-31"]);
+        assert_eq!(days("FREQ=DAILY;INTERVAL=40"), ["01-31", "03-11", "04-20"]);
+        // 2024-01-31 is a wednesday
+        assert_eq!(
+            days("FREQ=WEEKLY;INTERVAL=6;BYDAY=TU,TH")[..3],
+            ["02-01", "03-12", "03-14"]
+        );
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=1MO", Tz::UTC).is_err());
+        assert!(Recurrence::parse("FREQ=HOURLY", Tz::UTC).is_err());
+    }
+}
@@ -1,23 +1,54 @@
-use std::{error::Error, sync::Arc};
+use std::{error::Error, sync::Arc, time::Duration};
 
+use chrono::Utc;
+use chrono_tz::Tz;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite};
 use teloxide::{
     dispatching::{DpHandlerDescription, UpdateFilterExt},
     dptree::Endpoint,
-    net::Download,
-    payloads::EditMessageTextSetters,
+    payloads::{EditMessageReplyMarkupSetters, EditMessageTextSetters},
     prelude::DependencyMap,
     requests::Requester,
-    types::{CallbackQuery, InlineKeyboardMarkup, Update},
-    Bot,
+    types::{CallbackQuery, ChatId, InlineKeyboardMarkup, MessageId, Update},
+    Bot, RequestError,
+};
+use tokio::{
+    select,
+    time::{self, Instant},
 };
-use tokio::fs::File;
 
 use crate::{
+    announcement::{prepare_audio, Room, Target},
+    audio::{format_duration, format_progress, processor::AudioProcessor},
+    audio_cache::AudioCacheEntry,
+    backoff::RetryPolicy,
+    clip::Clip,
     config::AppConfig,
-    inline_data_keyboard::{InlineDataKeyboard, InlineDataKeyboardButton},
-    player::Player,
+    dialogues::{template::continue_template, DialogueDependency, DialogueStorage, State},
+    handle_calendar::ImportedJob,
+    handle_voice_message::offer_keyboard,
+    inline_data_keyboard::{parse_page_data, InlineDataKeyboard, InlineDataKeyboardButton},
+    player::{PlaybackState, Player, Repeat},
+    privacy::forget_user,
+    retention::{purge, RetentionPolicy},
+    room_group::RoomGroup,
+    room_tree::{move_room, LocatedRoom},
+    routine::{remove_holiday, reschedule_routines, Routine},
+    schedule::{format_time, Schedule},
+    template::Template,
+};
+
+/// Keeps long lists of scheduled announcements within a few rows.
+const SCHEDULED_PAGE_SIZE: usize = 8;
+
+const EDIT_RETRY: RetryPolicy = RetryPolicy {
+    initial_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(5),
+    max_attempts: Some(4),
+    max_elapsed: Some(Duration::from_secs(15)),
+    jitter: true,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -27,11 +58,497 @@ pub enum CallbackType {
     },
     PlayAudio {
         room_name: String,
-        voice_file_id: String,
+        file_unique_id: String,
+        #[serde(default)]
+        repeat: Repeat,
+    },
+    PlayAudioIn {
+        room_names: Vec<String>,
+        file_unique_id: String,
+        #[serde(default)]
+        repeat: Repeat,
+    },
+    PlayGroup {
+        group_name: String,
+        file_unique_id: String,
+        #[serde(default)]
+        repeat: Repeat,
+    },
+    SetRepeat {
+        file_unique_id: String,
+        repeat: Repeat,
+        #[serde(default)]
+        selection: Option<Vec<String>>,
+        #[serde(default)]
+        location: Vec<String>,
+    },
+    SelectRooms {
+        file_unique_id: String,
+        repeat: Repeat,
+        selection: Option<Vec<String>>,
+        #[serde(default)]
+        location: Vec<String>,
     },
+    Browse {
+        file_unique_id: String,
+        repeat: Repeat,
+        selection: Option<Vec<String>>,
+        location: Vec<String>,
+    },
+    TogglePause,
     RoomDel {
         name: String,
     },
+    RoomMove {
+        name: String,
+        offset: i64,
+    },
+    RoomGroupDel {
+        name: String,
+    },
+    OfferClip {
+        name: String,
+    },
+    RenameClip {
+        name: String,
+    },
+    ClipDel {
+        name: String,
+    },
+    UseTemplate {
+        name: String,
+    },
+    CancelSchedule {
+        id: i64,
+    },
+    TemplateDel {
+        name: String,
+    },
+    RoutineDel {
+        id: i64,
+    },
+    HolidayDel {
+        date: String,
+    },
+    ImportSchedules,
+    CancelImport,
+    StoragePurge {
+        all: bool,
+    },
+    ForgetUser {
+        user_id: i64,
+    },
+}
+
+/// The buttons below a playing audio, toggling pause keeps the data of each button.
+fn playback_keyboard(paused: bool) -> serde_json::Result<InlineDataKeyboard> {
+    Ok(InlineDataKeyboard::new().buttons(vec![
+        InlineDataKeyboardButton {
+            text: match paused {
+                true => "Resume".into(),
+                false => "Pause".into(),
+            },
+            data: serde_json::to_string(&CallbackType::TogglePause)?,
+        },
+        InlineDataKeyboardButton {
+            text: "Stop".into(),
+            data: serde_json::to_string(&CallbackType::StopAudio { id: "todo".into() })?,
+        },
+    ]))
+}
+
+/// Buttons moving each room up or down in the order rooms are listed in.
+pub fn room_order_keyboard(room_names: &[String]) -> serde_json::Result<InlineDataKeyboard> {
+    let buttons = room_names
+        .iter()
+        .flat_map(|name| [("↑", -1), ("↓", 1)].map(|(arrow, offset)| (name, arrow, offset)))
+        .map(|(name, arrow, offset)| {
+            Ok(InlineDataKeyboardButton {
+                text: format!("{} {}", arrow, name),
+                data: serde_json::to_string(&CallbackType::RoomMove {
+                    name: name.to_owned(),
+                    offset,
+                })?,
+            })
+        })
+        .try_collect()?;
+    Ok(InlineDataKeyboard::new().chunk_size(2).buttons(buttons))
+}
+
+/// A button cancelling each of the announcements, which describes it, so long lists fit the
+/// message by turning pages.
+pub fn scheduled_keyboard(
+    schedules: &[Schedule],
+    timezone: Tz,
+) -> serde_json::Result<InlineDataKeyboard> {
+    let buttons = schedules
+        .iter()
+        .map(|schedule| {
+            Ok(InlineDataKeyboardButton {
+                text: format!(
+                    "✕ {} in {}",
+                    format_time(schedule.run_at, timezone),
+                    schedule.room_name
+                ),
+                data: serde_json::to_string(&CallbackType::CancelSchedule { id: schedule.id })?,
+            })
+        })
+        .try_collect()?;
+    Ok(InlineDataKeyboard::new()
+        .chunk_size(1)
+        .page_size(SCHEDULED_PAGE_SIZE)
+        .buttons(buttons))
+}
+
+pub fn scheduled_text(timezone: Tz) -> String {
+    format!(
+        "Scheduled announcements ({}), tap one to cancel it:",
+        timezone
+    )
+}
+
+/// Edits the message of a keyboard, dropping the data of its old buttons.
+async fn edit_message(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: String,
+    reply_markup: Option<InlineKeyboardMarkup>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    EDIT_RETRY
+        .retry("editing message", || {
+            let mut edit_msg = bot.edit_message_text(chat_id, message_id, text.clone());
+            if let Some(markup) = &reply_markup {
+                edit_msg = edit_msg.reply_markup(markup.clone())
+            }
+            async move { edit_msg.await }
+        })
+        .await?;
+
+    InlineDataKeyboard::remove_from_db(db, &message_id).await?;
+
+    Ok(())
+}
+
+/// Shows the keyboard offering an audio again, i. e. with other rooms selected or on another level.
+#[allow(clippy::too_many_arguments)]
+async fn redraw_offer(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    chat_id: ChatId,
+    message_id: MessageId,
+    file_unique_id: &str,
+    repeat: Repeat,
+    selection: Option<&[String]>,
+    location: &[String],
+    page: usize,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let keyboard = offer_keyboard(
+        &LocatedRoom::all(db).await?,
+        &RoomGroup::names(db).await?,
+        file_unique_id,
+        repeat,
+        selection,
+        location,
+    )?
+    .page(page);
+    bot.edit_message_reply_markup(chat_id, message_id)
+        .reply_markup(keyboard.build_inline_keyboard_markup())
+        .await?;
+    InlineDataKeyboard::remove_from_db(db, &message_id).await?;
+    keyboard.insert_into_db(db, &message_id).await?;
+    Ok(())
+}
+
+/// Plays an audio in the rooms one after another, showing the progress in the message.
+#[allow(clippy::too_many_arguments)]
+async fn play_audio(
+    app_config: &AppConfig,
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    player: &Player,
+    audio_processor: &AudioProcessor,
+    chat_id: ChatId,
+    message_id: MessageId,
+    target: Target,
+    file_unique_id: &str,
+    repeat: Repeat,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let edit_query_message = |text: String, reply_markup: Option<InlineKeyboardMarkup>| {
+        edit_message(bot, db, chat_id, message_id, text, reply_markup)
+    };
+
+    let player_lock = match player.try_lock() {
+        Err(_) => {
+            edit_query_message("Another audio is already being played.".into(), None).await?;
+            return Ok(());
+        }
+        Ok(player) => player,
+    };
+
+    edit_query_message(format!("Preparing audio for: {}", target.label()), None).await?;
+
+    let Some(entry) = AudioCacheEntry::find(db, file_unique_id).await? else {
+        edit_query_message(
+            "This audio is no longer available, please send it again.".into(),
+            None,
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let rooms = target.resolve(db, app_config).await?;
+    if rooms.is_empty() {
+        let text = match target {
+            Target::Rooms(room_names) => {
+                format!("The room {} no longer exists.", room_names.join(", "))
+            }
+            Target::Group(name) => format!("The group {} no longer exists or has no rooms.", name),
+        };
+        edit_query_message(text, None).await?;
+        return Ok(());
+    }
+    let label = rooms.iter().map(|room| &room.name).join(", ");
+
+    let mut player_lock = Some(player_lock);
+    let mut elapsed = Duration::ZERO;
+    let mut repetitions = repeat.count;
+    for (index, room) in rooms.iter().enumerate() {
+        // the player is free in between rooms, so someone else may take it
+        let Some(player_lock) = player_lock.take().or_else(|| player.try_lock().ok()) else {
+            edit_query_message(
+                format!(
+                    "Another audio took over, not played in: {}",
+                    rooms[index..].iter().map(|room| &room.name).join(", ")
+                ),
+                None,
+            )
+            .await?;
+            return Ok(());
+        };
+        let room_name = match rooms.len() {
+            1 => room.name.clone(),
+            count => format!("{} ({} of {})", room.name, index + 1, count),
+        };
+
+        // the audio was downloaded and usually processed while the keyboard was shown,
+        // whatever is left runs while the mixer switches
+        let prepare = prepare_audio(bot, db, app_config, audio_processor, &entry, room);
+        // a tap on the wrong room can still be cancelled before the mixer switches
+        let arming_window = match index {
+            0 => Duration::from_millis(app_config.env.arming_window),
+            _ => Duration::ZERO,
+        };
+        let arm = async {
+            if arming_window.is_zero() {
+                return Ok(None);
+            }
+            let deadline = Instant::now() + arming_window;
+            let arming_text = || {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                format!(
+                    "Playing audio in: {} in {}s",
+                    label,
+                    remaining.as_secs_f64().ceil()
+                )
+            };
+            let cancel_keyboard =
+                InlineDataKeyboard::new().buttons(vec![InlineDataKeyboardButton {
+                    text: "Cancel".into(),
+                    data: serde_json::to_string(&CallbackType::StopAudio { id: "todo".into() })?,
+                }]);
+            let cancel_markup = cancel_keyboard.build_inline_keyboard_markup();
+            edit_query_message(arming_text(), Some(cancel_markup.clone())).await?;
+            cancel_keyboard.insert_into_db(db, &message_id).await?;
+
+            let armed = player_lock.arm(arming_window);
+            tokio::pin!(armed);
+            // the countdown shows whole seconds, Telegram slows it down with RetryAfter if needed
+            let interval = Duration::from_secs(1);
+            let mut countdown = time::interval_at(Instant::now() + interval, interval);
+            countdown.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+            let mut last_text = arming_text();
+            let mut next_edit = Instant::now();
+            loop {
+                select! {
+                    cancelled_by = &mut armed => {
+                        return Result::<_, Box<dyn Error + Send + Sync>>::Ok(cancelled_by)
+                    }
+                    _ = countdown.tick() => {
+                        let text = arming_text();
+                        if text == last_text || Instant::now() < next_edit {
+                            continue;
+                        }
+                        let res = bot
+                            .edit_message_text(chat_id, message_id, text.clone())
+                            .reply_markup(cancel_markup.clone())
+                            .await;
+                        match res {
+                            Ok(_) => last_text = text,
+                            Err(RequestError::RetryAfter(seconds)) => {
+                                next_edit = Instant::now() + seconds.duration();
+                            }
+                            Err(err) => log::warn!("failed to update arming countdown: {}", err),
+                        }
+                    }
+                }
+            }
+        };
+        // returns the text to show if playback can't go on
+        let switch_preset = async {
+            if let Some(cancelled_by) = arm.await? {
+                return Ok(Some(format!(
+                    "Cancelled audio in: {} by {}",
+                    label, cancelled_by
+                )));
+            }
+            if app_config.env.mock_ahm_connection {
+                log::warn!("Skipping preset config because MOCK_AHM_CONNECTION is enabled.");
+                return Ok(None);
+            }
+            if let Err(err) = player.set_channel(room.preset as u16).await {
+                log::error!("failed to switch channels: {}", err);
+                return Ok(Some("Failed to switch channels, the mixer ain't responding :/ Please try this again later.".into()));
+            }
+            Result::<_, Box<dyn Error + Send + Sync>>::Ok(None)
+        };
+        let (prepared, switched) = tokio::join!(prepare, switch_preset);
+        if let Some(text) = switched? {
+            edit_query_message(text, None).await?;
+            return Ok(());
+        }
+        let (entry, processed) = match prepared {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                log::error!("failed to prepare audio: {}", err);
+                edit_query_message(
+                    "Failed to download the audio :/ Please try this again later.".into(),
+                    None,
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        let total = processed.duration.or(entry.duration());
+        let playing_text = |state: Option<PlaybackState>| {
+            let mut text = match state.is_some_and(|state| state.paused_at.is_some()) {
+                true => format!("Paused audio in: {}", room_name),
+                false => format!("Playing audio in: {}", room_name),
+            };
+            let (repetition, repetitions) = state.map_or((1, repeat.count), |state| {
+                (state.repetition, state.repetitions)
+            });
+            if repetitions > 1 {
+                text += &format!("\nrepetition {} of {}", repetition, repetitions);
+            }
+            let elapsed = state.map_or(Duration::ZERO, |state| state.elapsed());
+            match total {
+                Some(total) => text + "\n" + &format_progress(elapsed, total),
+                None => text + &format!(" ({})", format_duration(elapsed)),
+            }
+        };
+        let mut last_text = playing_text(None);
+        let keyboard = playback_keyboard(false)?;
+        edit_query_message(
+            last_text.clone(),
+            Some(keyboard.build_inline_keyboard_markup()),
+        )
+        .await?;
+        keyboard.insert_into_db(db, &message_id).await?;
+
+        let audio_path = processed
+            .path
+            .to_str()
+            .ok_or("failed to construct voice file path")?;
+
+        let mut state = player.subscribe();
+        let started = Instant::now();
+        let interval = Duration::from_millis(app_config.env.progress_interval);
+        let mut progress = time::interval_at(started + interval, interval);
+        progress.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        // telegram may ask us to slow down, progress edits are skipped until then
+        let mut next_edit = started;
+        let play = player_lock.play_audio_file(audio_path, repeat, &room.overrides);
+        tokio::pin!(play);
+        let played = loop {
+            select! {
+                res = &mut play => break res,
+                _ = progress.tick() => {}
+                // pausing, resuming and the next repetition are shown right away
+                _ = state.changed() => {}
+            }
+
+            let current = *state.borrow_and_update();
+            let text = playing_text(current);
+            if text == last_text || Instant::now() < next_edit {
+                continue;
+            }
+            let paused = current.is_some_and(|state| state.paused_at.is_some());
+            let res = bot
+                .edit_message_text(chat_id, message_id, text.clone())
+                .reply_markup(playback_keyboard(paused)?.build_inline_keyboard_markup())
+                .await;
+            match res {
+                Ok(_) => last_text = text,
+                Err(RequestError::RetryAfter(seconds)) => {
+                    next_edit = Instant::now() + seconds.duration();
+                }
+                Err(err) => log::warn!("failed to update playback progress: {}", err),
+            }
+        };
+
+        let report = match played {
+            Ok(report) => report,
+            Err(err) => {
+                log::error!("failed to play audio: {}", err);
+                edit_query_message(format!("Failed to play audio in: {}", room_name), None).await?;
+                return Ok(());
+            }
+        };
+        elapsed += report.elapsed;
+        repetitions = report.repetitions;
+
+        if let Some(stopped_by) = report.stopped_by {
+            // stopping one room stops the whole announcement
+            let mut text = format!(
+                "Stopped audio in: {} after {}",
+                room.name,
+                format_duration(report.elapsed)
+            );
+            if let Some(total) = total {
+                text += &format!(" of {}", format_duration(total));
+            }
+            if report.repetitions > 1 {
+                text += &format!(
+                    " in repetition {} of {}",
+                    report.repetition, report.repetitions
+                );
+            }
+            text += &format!(" by {}", stopped_by);
+            if index + 1 < rooms.len() {
+                text += &format!(
+                    "\nNot played in: {}",
+                    rooms[index + 1..].iter().map(|room| &room.name).join(", ")
+                );
+            }
+            edit_query_message(text, None).await?;
+            return Ok(());
+        }
+    }
+
+    let played_text = match repetitions {
+        1 => format!("Played audio in: {} ({})", label, format_duration(elapsed)),
+        repetitions => format!(
+            "Played audio {}× in: {} ({})",
+            repetitions,
+            label,
+            format_duration(elapsed)
+        ),
+    };
+    edit_query_message(played_text, None).await?;
+    Ok(())
 }
 
 async fn callback_endpoint(
@@ -39,6 +556,8 @@ async fn callback_endpoint(
     bot: Bot,
     db: Pool<Sqlite>,
     player: Arc<Player>,
+    audio_processor: Arc<AudioProcessor>,
+    dialogue_storage: DialogueStorage,
     q: CallbackQuery,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let message = match q.message {
@@ -57,6 +576,14 @@ async fn callback_endpoint(
             return Ok(());
         }
     };
+    if let Some(page) = parse_page_data(query_data) {
+        if let Some(markup) = InlineDataKeyboard::turn_page(&db, &message.id, page).await? {
+            bot.edit_message_reply_markup(chat_id, message.id)
+                .reply_markup(markup)
+                .await?;
+        }
+        return Ok(());
+    }
     let button_index = match query_data.parse::<i64>() {
         Ok(button_index) => button_index,
         Err(_) => {
@@ -77,16 +604,8 @@ async fn callback_endpoint(
     .fetch_one(&db)
     .await?;
 
-    let edit_query_message = |text: String, reply_markup: Option<InlineKeyboardMarkup>| async {
-        let mut edit_msg = bot.edit_message_text(chat_id, message.id, text);
-        if let Some(markup) = reply_markup {
-            edit_msg = edit_msg.reply_markup(markup)
-        }
-        edit_msg.await?;
-
-        InlineDataKeyboard::remove_from_db(&db, &message.id).await?;
-
-        Result::<(), Box<dyn Error + Send + Sync>>::Ok(())
+    let edit_query_message = |text: String, reply_markup: Option<InlineKeyboardMarkup>| {
+        edit_message(&bot, &db, chat_id, message.id, text, reply_markup)
     };
 
     let cb_type: CallbackType = match serde_json::from_str(&button_data.data) {
@@ -116,81 +635,411 @@ async fn callback_endpoint(
             sqlx::query!("DELETE FROM rooms WHERE name = ?", name)
                 .execute(&db)
                 .await?;
+            RoomGroup::remove_room(&db, &name).await?;
             edit_query_message(format!("Deleted room {}.", name), None).await?;
         }
-        CallbackType::PlayAudio {
-            room_name,
-            voice_file_id,
-        } => {
-            let player_lock = match player.try_lock() {
-                Err(_) => {
-                    edit_query_message("Another audio is already being played.".into(), None)
+        CallbackType::RoomMove { name, offset } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
+
+            if !move_room(&db, &name, offset).await? {
+                return Ok(());
+            }
+            let room_names: Vec<String> = LocatedRoom::all(&db)
+                .await?
+                .into_iter()
+                .map(|room| room.name)
+                .collect();
+            let page = InlineDataKeyboard::current_page(&db, &message.id).await?;
+            let keyboard = room_order_keyboard(&room_names)?.page(page);
+            bot.edit_message_reply_markup(chat_id, message.id)
+                .reply_markup(keyboard.build_inline_keyboard_markup())
+                .await?;
+            InlineDataKeyboard::remove_from_db(&db, &message.id).await?;
+            keyboard.insert_into_db(&db, &message.id).await?;
+        }
+        CallbackType::RoomGroupDel { name } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
+
+            RoomGroup::delete(&db, &name).await?;
+            edit_query_message(format!("Deleted group {}.", name), None).await?;
+        }
+        CallbackType::OfferClip { name } => {
+            let entry = match Clip::find(&db, &name).await? {
+                Some(clip) => AudioCacheEntry::find(&db, &clip.unique_id).await?,
+                None => {
+                    edit_query_message(format!("The clip {} no longer exists.", name), None)
                         .await?;
                     return Ok(());
                 }
-                Ok(player) => player,
             };
+            let Some(entry) = entry else {
+                edit_query_message(
+                    format!("The audio of clip {} is gone, please save it again.", name),
+                    None,
+                )
+                .await?;
+                return Ok(());
+            };
+            let room_names: Vec<String> = sqlx::query!("SELECT name FROM rooms")
+                .fetch_all(&db)
+                .await?
+                .into_iter()
+                .map(|room| room.name)
+                .collect();
+            if room_names.is_empty() {
+                edit_query_message("No rooms were defined yet to play this in.".into(), None)
+                    .await?;
+                return Ok(());
+            }
+
+            let text = match entry.duration() {
+                Some(duration) => format!(
+                    "Where should I play {}? ({})",
+                    name,
+                    format_duration(duration)
+                ),
+                None => format!("Where should I play {}?", name),
+            };
+            let keyboard = offer_keyboard(
+                &LocatedRoom::all(&db).await?,
+                &RoomGroup::names(&db).await?,
+                &entry.unique_id,
+                Repeat::default(),
+                None,
+                &[],
+            )?;
+            edit_query_message(text, Some(keyboard.build_inline_keyboard_markup())).await?;
+            keyboard.insert_into_db(&db, &message.id).await?;
+
+            audio_processor.prefetch(entry.path(&app_config.audio_dir), room_names);
+        }
+        CallbackType::RenameClip { name } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
 
-            let stop_keyboard = InlineDataKeyboard::new().buttons(vec![InlineDataKeyboardButton {
-                text: "Stop".into(),
-                data: serde_json::to_string(&CallbackType::StopAudio { id: "todo".into() })?,
-            }]);
             edit_query_message(
-                format!("Playing audio in: {}", room_name),
-                Some(stop_keyboard.build_inline_keyboard_markup()),
+                format!("Please send me the new name of clip {}.", name),
+                None,
             )
             .await?;
-            stop_keyboard.insert_into_db(&db, &message.id).await?;
+            DialogueDependency::new(dialogue_storage, chat_id)
+                .update(State::ReceiveClipName { name })
+                .await?;
+        }
+        CallbackType::ClipDel { name } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
 
-            if app_config.env.mock_ahm_connection {
-                log::warn!("Skipping preset config because MOCK_AHM_CONNECTION is enabled.");
-            } else {
-                let preset = sqlx::query!("SELECT preset FROM rooms WHERE name = ?", room_name)
-                    .fetch_one(&db)
-                    .await?
-                    .preset as u16;
-                if let Err(err) = player.set_channel(preset).await {
-                    log::error!("failed to switch channels: {}", err);
-                    edit_query_message(
-                        "Failed to switch channels, the mixer ain't responding :/ Please try this again later.".into(),
-                        None
-                    )
-                    .await?;
-                    return Ok(());
-                }
+            let routines = Routine::using_clip(&db, &name).await?;
+            if !routines.is_empty() {
+                edit_query_message(
+                    format!(
+                        "Clip {} is played by the routines {}, please delete them first.",
+                        name,
+                        routines.join(", ")
+                    ),
+                    None,
+                )
+                .await?;
+                return Ok(());
             }
 
-            let file = bot.get_file(&voice_file_id).await?;
-            let name = file
-                .path
-                .split("/")
-                .last()
-                .ok_or("failed to get voice file name")?;
-            let dst_path = app_config.audio_dir.join(name);
+            Clip::delete(&db, &name).await?;
+            edit_query_message(format!("Deleted clip {}.", name), None).await?;
+        }
+        CallbackType::CancelSchedule { id } => {
+            let Some(schedule) = Schedule::find(&db, id).await? else {
+                edit_query_message(
+                    "This announcement already ran or was cancelled.".into(),
+                    None,
+                )
+                .await?;
+                return Ok(());
+            };
+            if schedule.created_by != chat_id.0 && !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
 
-            match File::create_new(&dst_path).await {
-                Ok(mut dst) => {
-                    bot.download_file(&file.path, &mut dst).await?;
-                    dst.sync_all().await?;
-                }
-                Err(err) => match err.kind() {
-                    std::io::ErrorKind::AlreadyExists => {}
-                    _ => return Err(Box::new(err)),
-                },
+            Schedule::delete(&db, id).await?;
+            let cancelled = format!(
+                "Cancelled the announcement in: {} at {}.",
+                schedule.room_name,
+                format_time(schedule.run_at, app_config.timezone)
+            );
+            // the others stay on the list
+            let schedules = match app_config.is_admin(&chat_id.0) {
+                true => Schedule::all(&db).await?,
+                false => Schedule::created_by(&db, chat_id.0).await?,
+            };
+            if schedules.is_empty() {
+                edit_query_message(cancelled, None).await?;
+                return Ok(());
+            }
+            let page = InlineDataKeyboard::current_page(&db, &message.id).await?;
+            let keyboard = scheduled_keyboard(&schedules, app_config.timezone)?.page(page);
+            edit_query_message(
+                format!("{}\n\n{}", cancelled, scheduled_text(app_config.timezone)),
+                Some(keyboard.build_inline_keyboard_markup()),
+            )
+            .await?;
+            keyboard.insert_into_db(&db, &message.id).await?;
+        }
+        CallbackType::UseTemplate { name } => {
+            let Some(template) = Template::find(&db, &name).await? else {
+                edit_query_message("This template no longer exists.".into(), None).await?;
+                return Ok(());
+            };
+            if app_config.env.tts_command.is_none() {
+                edit_query_message("Text-to-speech isn't enabled.".into(), None).await?;
+                return Ok(());
+            }
+
+            edit_query_message(format!("Template {}:\n{}", name, template.text), None).await?;
+            let dialogue = DialogueDependency::new(dialogue_storage, chat_id);
+            continue_template(
+                &bot,
+                &db,
+                &app_config,
+                &audio_processor,
+                &dialogue,
+                &template,
+                Vec::new(),
+            )
+            .await?;
+        }
+        CallbackType::TemplateDel { name } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
+
+            sqlx::query!("DELETE FROM templates WHERE name = ?", name)
+                .execute(&db)
+                .await?;
+            edit_query_message(format!("Deleted template {}.", name), None).await?;
+        }
+        CallbackType::RoutineDel { id } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
+
+            let Some(routine) = Routine::find(&db, id).await? else {
+                edit_query_message("This routine no longer exists.".into(), None).await?;
+                return Ok(());
             };
+            Routine::delete(&db, id).await?;
+            edit_query_message(format!("Deleted routine {}.", routine.name), None).await?;
+        }
+        CallbackType::HolidayDel { date } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
+
+            remove_holiday(&db, &date).await?;
+            reschedule_routines(&db, Utc::now().with_timezone(&app_config.timezone)).await?;
+            edit_query_message(format!("Removed holiday {}.", date), None).await?;
+        }
+        CallbackType::ImportSchedules => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
 
-            let audio_path = dst_path
-                .to_str()
-                .ok_or("failed to construct voice file path")?;
+            // the preview may have been waiting for a while
+            let now = Utc::now().timestamp();
+            let mut imported = 0;
+            let mut duplicates = 0;
+            let mut gone = 0;
+            for job in ImportedJob::take_pending(&db, &message.id)
+                .await?
+                .into_iter()
+                .filter(|job| job.run_at > now)
+            {
+                let (Some(clip), Some(_)) = (
+                    Clip::find(&db, &job.clip_name).await?,
+                    Room::find(&db, &job.room_name).await?,
+                ) else {
+                    gone += 1;
+                    continue;
+                };
+                // importing a calendar again must not play its announcements twice
+                if Schedule::exists(&db, &clip.unique_id, &job.room_name, job.run_at).await? {
+                    duplicates += 1;
+                    continue;
+                }
+                Schedule::insert(&db, &clip.unique_id, &job.room_name, job.run_at, chat_id.0)
+                    .await?;
+                imported += 1;
+            }
+            let mut text = format!(
+                "Imported {} announcements. Use /scheduled to see them.",
+                imported
+            );
+            if duplicates > 0 {
+                text += &format!("\nSkipped {} which were already scheduled.", duplicates);
+            }
+            if gone > 0 {
+                text += &format!(
+                    "\nSkipped {} whose clip or room was deleted in the meantime.",
+                    gone
+                );
+            }
+            edit_query_message(text, None).await?;
+        }
+        CallbackType::CancelImport => {
+            ImportedJob::take_pending(&db, &message.id).await?;
+            edit_query_message("Cancelled the import.".into(), None).await?;
+        }
+        CallbackType::StoragePurge { all } => {
+            if !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
 
-            player_lock.play_audio_file(&audio_path).await?;
+            let policy = RetentionPolicy::from(app_config.as_ref());
+            let report = purge(&db, &app_config.audio_dir, &policy, all).await?;
+            edit_query_message(format!("Storage cleanup {}.", report), None).await?;
+        }
+        CallbackType::ForgetUser { user_id } => {
+            if user_id != chat_id.0 && !app_config.is_admin(&chat_id.0) {
+                edit_query_message("Insufficient permission.".into(), None).await?;
+                return Ok(());
+            }
 
-            edit_query_message(format!("Played audio in: {}", room_name), None).await?;
-            InlineDataKeyboard::remove_from_db(&db, &message.id).await?;
+            let report = forget_user(&db, &app_config.audio_dir, user_id).await?;
+            edit_query_message(report.to_string(), None).await?;
+        }
+        CallbackType::SetRepeat {
+            file_unique_id,
+            repeat,
+            selection,
+            location,
+        }
+        | CallbackType::SelectRooms {
+            file_unique_id,
+            repeat,
+            selection,
+            location,
+        } => {
+            let page = InlineDataKeyboard::current_page(&db, &message.id).await?;
+            redraw_offer(
+                &bot,
+                &db,
+                chat_id,
+                message.id,
+                &file_unique_id,
+                repeat,
+                selection.as_deref(),
+                &location,
+                page,
+            )
+            .await?;
+        }
+        CallbackType::Browse {
+            file_unique_id,
+            repeat,
+            selection,
+            location,
+        } => {
+            redraw_offer(
+                &bot,
+                &db,
+                chat_id,
+                message.id,
+                &file_unique_id,
+                repeat,
+                selection.as_deref(),
+                &location,
+                0,
+            )
+            .await?;
+        }
+        CallbackType::PlayAudio {
+            room_name,
+            file_unique_id,
+            repeat,
+        } => {
+            play_audio(
+                &app_config,
+                &bot,
+                &db,
+                &player,
+                &audio_processor,
+                chat_id,
+                message.id,
+                Target::Rooms(vec![room_name]),
+                &file_unique_id,
+                repeat,
+            )
+            .await?;
+        }
+        CallbackType::PlayAudioIn {
+            room_names,
+            file_unique_id,
+            repeat,
+        } => {
+            play_audio(
+                &app_config,
+                &bot,
+                &db,
+                &player,
+                &audio_processor,
+                chat_id,
+                message.id,
+                Target::Rooms(room_names),
+                &file_unique_id,
+                repeat,
+            )
+            .await?;
+        }
+        CallbackType::PlayGroup {
+            group_name,
+            file_unique_id,
+            repeat,
+        } => {
+            play_audio(
+                &app_config,
+                &bot,
+                &db,
+                &player,
+                &audio_processor,
+                chat_id,
+                message.id,
+                Target::Group(group_name),
+                &file_unique_id,
+                repeat,
+            )
+            .await?;
+        }
+        CallbackType::TogglePause => {
+            let paused = player
+                .subscribe()
+                .borrow()
+                .is_some_and(|state| state.paused_at.is_some());
+            let res = match paused {
+                true => player.resume_playing().await,
+                false => player.pause_playing().await,
+            };
+            if let Err(err) = res {
+                log::warn!("failed to toggle pause: {}", err);
+            }
         }
         CallbackType::StopAudio { id: _id } => {
             // todo: associate id with audio?
-            let _ = player.stop_playing().await;
+            let _ = player.stop_playing(q.from.full_name()).await;
         }
     }
 
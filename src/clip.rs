@@ -0,0 +1,71 @@
+use sqlx::{Pool, Sqlite};
+
+/// A saved audio which is kept until it is deleted, regardless of the retention policy.
+pub struct Clip {
+    pub name: String,
+    pub unique_id: String,
+}
+
+impl Clip {
+    pub async fn find(db: &Pool<Sqlite>, name: &str) -> sqlx::Result<Option<Clip>> {
+        sqlx::query_as!(
+            Clip,
+            "SELECT name, unique_id FROM clips WHERE name = ?",
+            name
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    pub async fn all(db: &Pool<Sqlite>) -> sqlx::Result<Vec<Clip>> {
+        sqlx::query_as!(Clip, "SELECT name, unique_id FROM clips ORDER BY name")
+            .fetch_all(db)
+            .await
+    }
+
+    /// Saves the audio under a new name, returns `false` if the name is taken.
+    pub async fn insert(&self, db: &Pool<Sqlite>, user_id: i64) -> sqlx::Result<bool> {
+        let res = sqlx::query!(
+            "INSERT INTO clips (name, unique_id, created_by) VALUES (?, ?, ?)
+                ON CONFLICT(name) DO NOTHING",
+            self.name,
+            self.unique_id,
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Returns `false` if the clip is gone or the new name is taken.
+    /// Routines playing the clip follow the new name.
+    pub async fn rename(db: &Pool<Sqlite>, name: &str, new_name: &str) -> sqlx::Result<bool> {
+        let mut tx = db.begin().await?;
+        let res = sqlx::query!(
+            "UPDATE OR IGNORE clips SET name = ? WHERE name = ?",
+            new_name,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query!(
+            "UPDATE routines SET clip_name = ? WHERE clip_name = ?",
+            new_name,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn delete(db: &Pool<Sqlite>, name: &str) -> sqlx::Result<()> {
+        sqlx::query!("DELETE FROM clips WHERE name = ?", name)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
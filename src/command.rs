@@ -1,5 +1,6 @@
 use std::{error::Error, sync::Arc};
 
+use chrono::{NaiveDate, Utc};
 use itertools::Itertools;
 use sqlx::{Pool, Sqlite};
 use teloxide::{
@@ -10,12 +11,22 @@ use teloxide::{
 };
 
 use crate::{
-    callback_handler::CallbackType,
+    audio::processor::AudioProcessor,
+    callback_handler::{room_order_keyboard, scheduled_keyboard, scheduled_text, CallbackType},
+    clip::Clip,
     config::AppConfig,
     dialogues,
-    handle_replies::handle_replies,
+    handle_calendar::{shorten, MAX_MESSAGE_LENGTH},
+    handle_replies::{handle_replies, save_clip, schedule_reply},
+    handle_text_message::handle_text_message,
     inline_data_keyboard::{InlineDataKeyboard, InlineDataKeyboardButton},
-    player::Player,
+    player::{PauseAudioError, Player},
+    retention::{self, format_file_size, RetentionPolicy},
+    room_group::RoomGroup,
+    room_tree::LocatedRoom,
+    routine::{add_holiday, holidays, reschedule_routines, Routine},
+    schedule::{format_time, Schedule},
+    template::Template,
 };
 
 #[derive(BotCommands, Clone)]
@@ -25,7 +36,10 @@ use crate::{
 
 - send me a voice message
 - send me an audio file
+- send me a text to read out, if text-to-speech is enabled
 - reply to an older message with /play
+- play a saved clip with /clips
+- reply to an older message with /schedule to play it later
 
 Additionally these commands may be used:"
 )]
@@ -36,38 +50,145 @@ pub enum Command {
     Help,
     /// play the mentioned audio message
     Play,
+    /// read out a text
+    Say(String),
+    /// play the mentioned audio message later
+    Schedule,
+    /// list and cancel scheduled announcements
+    Scheduled,
+    /// save the mentioned audio message as a clip
+    Save(String),
+    /// play a saved clip
+    Clips,
+    /// rename a clip
+    ClipRename,
+    /// delete a clip
+    ClipDel,
+    /// read out a template
+    Template,
+    /// add or change a template
+    TemplateSet,
+    /// delete a template
+    TemplateDel,
+    /// list and delete recurring announcements
+    Routines,
+    /// add a recurring announcement
+    RoutineAdd,
+    /// list and remove days without routines
+    Holidays,
+    /// skip routines on a day, i. e. /holiday_add 2024-12-24
+    HolidayAdd(String),
     /// stop the currently playing audio
     Stop,
+    /// hold the currently playing audio
+    Pause,
+    /// continue the paused audio
+    Resume,
     /// list all rooms
     Rooms,
     /// link a room to a preset
     RoomSet,
     /// delete a room
     RoomDel,
+    /// change the order of rooms
+    RoomOrder,
+    /// list room groups
+    RoomGroups,
+    /// add or change a room group
+    RoomGroupSet,
+    /// delete a room group
+    RoomGroupDel,
     /// link a group of authorized users
     GroupLink,
+    /// show and clean up the audio storage
+    Storage,
+    /// delete all audios you sent
+    ForgetMe,
+    /// delete all audios a user sent
+    PurgeUser(String),
+}
+
+async fn confirm_forget(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    chat_id: ChatId,
+    user_id: i64,
+    text: String,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let keyboard = InlineDataKeyboard::new().buttons(vec![InlineDataKeyboardButton {
+        text: "Delete".into(),
+        data: serde_json::to_string(&CallbackType::ForgetUser { user_id })?,
+    }]);
+    let keyboard_msg = bot
+        .send_message(chat_id, text)
+        .reply_markup(keyboard.build_inline_keyboard_markup())
+        .await?;
+    keyboard.insert_into_db(db, &keyboard_msg.id).await?;
+    Ok(())
+}
+
+/// Sends a keyboard with a button per name.
+async fn select_keyboard(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    chat_id: ChatId,
+    names: Vec<String>,
+    text: &str,
+    cb_type: impl Fn(String) -> CallbackType,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let keyboard = InlineDataKeyboard::new().buttons(
+        names
+            .into_iter()
+            .map(|name| {
+                serde_json::Result::<InlineDataKeyboardButton>::Ok(InlineDataKeyboardButton {
+                    text: name.to_owned(),
+                    data: serde_json::to_string(&cb_type(name))?,
+                })
+            })
+            .try_collect()?,
+    );
+    let keyboard_msg = bot
+        .send_message(chat_id, text)
+        .reply_markup(keyboard.build_inline_keyboard_markup())
+        .await?;
+    keyboard.insert_into_db(db, &keyboard_msg.id).await?;
+    Ok(())
 }
 
 impl Command {
+    #[allow(clippy::too_many_arguments)]
     pub async fn execute(
         self,
         app_config: Arc<AppConfig>,
         bot: Bot,
         db: Pool<Sqlite>,
         player: Arc<Player>,
+        audio_processor: Arc<AudioProcessor>,
         msg: Message,
-        set_room_dialogue: dialogues::set_room::DialogueDependency,
+        dialogue: dialogues::DialogueDependency,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        set_room_dialogue.reset().await?;
+        dialogue.reset().await?;
 
         match self {
             Command::Start => {
                 bot.send_message(msg.chat.id, "Hello there,\n\nSend me a voice message and I'll announce it for you!\n\nUse /help for more information.").await?;
             }
             Command::Play => {
-                handle_replies(&bot, &db, &msg).await?;
+                handle_replies(&bot, &db, &app_config, &audio_processor, &msg).await?;
+            }
+            Command::Say(text) => {
+                handle_text_message(&bot, &db, &app_config, &audio_processor, msg.chat.id, &text)
+                    .await?;
             }
-            Command::Stop => match player.stop_playing().await {
+            Command::Stop => match player
+                .stop_playing(
+                    [msg.chat.first_name(), msg.chat.last_name()]
+                        .into_iter()
+                        .flatten()
+                        .join(" "),
+                )
+                .await
+            {
                 Err(err) => match err {
                     crate::player::StopAudioError::AlreadyStopped => {
                         bot.send_message(msg.chat.id, "No audio is being played at this time.")
@@ -79,17 +200,64 @@ impl Command {
                         .await?;
                 }
             },
+            Command::Pause => {
+                let text = match player.pause_playing().await {
+                    Ok(()) => "Paused the current audio. Use /resume to continue.".into(),
+                    Err(PauseAudioError::NotPlaying) => {
+                        "No audio is being played at this time.".into()
+                    }
+                    Err(PauseAudioError::AlreadyPaused) => "The audio is already paused.".into(),
+                    Err(err) => format!("Failed to pause the audio: {}", err),
+                };
+                bot.send_message(msg.chat.id, text).await?;
+            }
+            Command::Resume => {
+                let text = match player.resume_playing().await {
+                    Ok(()) => "Resumed the current audio.".into(),
+                    Err(PauseAudioError::NotPlaying) => {
+                        "No audio is being played at this time.".into()
+                    }
+                    Err(PauseAudioError::NotPaused) => "The audio isn't paused.".into(),
+                    Err(err) => format!("Failed to resume the audio: {}", err),
+                };
+                bot.send_message(msg.chat.id, text).await?;
+            }
             Command::Help => {
                 bot.send_message(msg.chat.id, Command::descriptions().to_string())
                     .await?;
             }
             Command::Rooms => {
-                let rooms = sqlx::query!("SELECT * FROM rooms").fetch_all(&db).await?;
+                let rooms = sqlx::query!("SELECT * FROM rooms ORDER BY sort_index, name")
+                    .fetch_all(&db)
+                    .await?;
                 let room_list = if rooms.len() > 0 {
                     "Rooms and presets:\n".to_owned()
                         + &rooms
                             .iter()
-                            .map(|room| format!("{} ↦ {}", room.name, room.preset))
+                            .map(|room| {
+                                let settings = [
+                                    room.location
+                                        .as_ref()
+                                        .map(|location| format!("in {}", location)),
+                                    room.start_delay.map(|delay| format!("delay {} ms", delay)),
+                                    room.volume.map(|volume| format!("volume {}%", volume)),
+                                    room.player_command
+                                        .as_ref()
+                                        .map(|command| format!("player \"{}\"", command)),
+                                    room.tts_voice
+                                        .as_ref()
+                                        .map(|voice| format!("voice {}", voice)),
+                                ]
+                                .into_iter()
+                                .flatten()
+                                .join(", ");
+                                match settings.is_empty() {
+                                    true => format!("{} ↦ {}", room.name, room.preset),
+                                    false => {
+                                        format!("{} ↦ {} ({})", room.name, room.preset, settings)
+                                    }
+                                }
+                            })
                             .join("\n")
                 } else {
                     "No rooms defined. Use /room_set to create one.".into()
@@ -105,9 +273,316 @@ impl Command {
 
                 bot.send_message(msg.chat.id, "Please send me a name for the room.")
                     .await?;
-                set_room_dialogue
-                    .update(dialogues::set_room::State::ReceiveRoomName)
+                dialogue.update(dialogues::State::ReceiveRoomName).await?;
+            }
+            Command::Schedule => {
+                schedule_reply(&bot, &db, &app_config, &dialogue, &msg).await?;
+            }
+            Command::Scheduled => {
+                // others only see their own announcements
+                let schedules = match app_config.is_admin(&msg.chat.id.0) {
+                    true => Schedule::all(&db).await?,
+                    false => Schedule::created_by(&db, msg.chat.id.0).await?,
+                };
+                if schedules.is_empty() {
+                    bot.send_message(
+                        msg.chat.id,
+                        "Nothing is scheduled. Reply to an audio with /schedule to add something.",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+
+                let keyboard = scheduled_keyboard(&schedules, app_config.timezone)?;
+                let keyboard_msg = bot
+                    .send_message(msg.chat.id, scheduled_text(app_config.timezone))
+                    .reply_markup(keyboard.build_inline_keyboard_markup())
+                    .await?;
+                keyboard.insert_into_db(&db, &keyboard_msg.id).await?;
+            }
+            Command::Save(name) => {
+                save_clip(&bot, &db, &app_config, &msg, &name).await?;
+            }
+            Command::Clips => {
+                let clips = Clip::all(&db).await?;
+                if clips.is_empty() {
+                    bot.send_message(
+                        msg.chat.id,
+                        "No clips saved yet. Reply to an audio with /save <name> to add one.",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                select_keyboard(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    clips.into_iter().map(|clip| clip.name).collect(),
+                    "Select a clip to play.",
+                    |name| CallbackType::OfferClip { name },
+                )
+                .await?;
+            }
+            Command::ClipRename | Command::ClipDel => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let clips = Clip::all(&db).await?;
+                if clips.is_empty() {
+                    bot.send_message(msg.chat.id, "No clips saved.").await?;
+                    return Ok(());
+                }
+                let names = clips.into_iter().map(|clip| clip.name).collect();
+                match self {
+                    Command::ClipRename => {
+                        select_keyboard(
+                            &bot,
+                            &db,
+                            msg.chat.id,
+                            names,
+                            "Select a clip to rename.",
+                            |name| CallbackType::RenameClip { name },
+                        )
+                        .await?
+                    }
+                    _ => {
+                        select_keyboard(
+                            &bot,
+                            &db,
+                            msg.chat.id,
+                            names,
+                            "Select a clip to delete.",
+                            |name| CallbackType::ClipDel { name },
+                        )
+                        .await?
+                    }
+                }
+            }
+            Command::Template => {
+                let templates = Template::all(&db).await?;
+                if templates.is_empty() {
+                    bot.send_message(msg.chat.id, "No templates defined yet.")
+                        .await?;
+                    return Ok(());
+                }
+                select_keyboard(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    templates
+                        .into_iter()
+                        .map(|template| template.name)
+                        .collect(),
+                    "Select a template to read out.",
+                    |name| CallbackType::UseTemplate { name },
+                )
+                .await?;
+            }
+            Command::TemplateSet => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                bot.send_message(msg.chat.id, "Please send me a name for the template.")
+                    .await?;
+                dialogue
+                    .update(dialogues::State::ReceiveTemplateName)
+                    .await?;
+            }
+            Command::TemplateDel => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let templates = Template::all(&db).await?;
+                if templates.is_empty() {
+                    bot.send_message(msg.chat.id, "No templates defined.")
+                        .await?;
+                    return Ok(());
+                }
+                select_keyboard(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    templates
+                        .into_iter()
+                        .map(|template| template.name)
+                        .collect(),
+                    "Select a template to delete.",
+                    |name| CallbackType::TemplateDel { name },
+                )
+                .await?;
+            }
+            Command::Routines => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let routines = Routine::all(&db).await?;
+                if routines.is_empty() {
+                    bot.send_message(
+                        msg.chat.id,
+                        "No routines defined. Use /routine_add to create one.",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+
+                let text = format!("Routines ({}):\n", app_config.timezone)
+                    + &routines
+                        .iter()
+                        .enumerate()
+                        .map(|(index, routine)| {
+                            let next_run = match routine.next_run_at {
+                                Some(next_run_at) => {
+                                    format!(
+                                        "next {}",
+                                        format_time(next_run_at, app_config.timezone)
+                                    )
+                                }
+                                None => "no upcoming run".into(),
+                            };
+                            format!(
+                                "{}. {}: {} in {}, {} ({})",
+                                index + 1,
+                                routine.name,
+                                routine.clip_name,
+                                routine.rooms.join(", "),
+                                routine.rule,
+                                next_run
+                            )
+                        })
+                        .join("\n");
+                // the buttons name the routines, as the list may be cut off
+                let keyboard = InlineDataKeyboard::new()
+                    .chunk_size(2)
+                    .page_size(10)
+                    .buttons(
+                        routines
+                            .iter()
+                            .map(|routine| {
+                                serde_json::Result::<InlineDataKeyboardButton>::Ok(
+                                    InlineDataKeyboardButton {
+                                        text: format!("Delete {}", routine.name),
+                                        data: serde_json::to_string(&CallbackType::RoutineDel {
+                                            id: routine.id,
+                                        })?,
+                                    },
+                                )
+                            })
+                            .try_collect()?,
+                    );
+                let keyboard_msg = bot
+                    .send_message(msg.chat.id, shorten(&text, MAX_MESSAGE_LENGTH))
+                    .reply_markup(keyboard.build_inline_keyboard_markup())
                     .await?;
+                keyboard.insert_into_db(&db, &keyboard_msg.id).await?;
+            }
+            Command::RoutineAdd => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                if Clip::all(&db).await?.is_empty() {
+                    bot.send_message(
+                        msg.chat.id,
+                        "Routines play saved clips. Reply to an audio with /save <name> to add one.",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+
+                bot.send_message(msg.chat.id, "Please send me a name for the routine.")
+                    .await?;
+                dialogue
+                    .update(dialogues::State::ReceiveRoutineName)
+                    .await?;
+            }
+            Command::Holidays => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let dates: Vec<String> = holidays(&db)
+                    .await?
+                    .into_iter()
+                    .sorted()
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .collect();
+                if dates.is_empty() {
+                    bot.send_message(
+                        msg.chat.id,
+                        "No holidays defined. Use /holiday_add <YYYY-MM-DD> to add one.",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                select_keyboard(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    dates,
+                    "Routines don't play on these days. Select one to remove it.",
+                    |date| CallbackType::HolidayDel { date },
+                )
+                .await?;
+            }
+            Command::HolidayAdd(date) => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let Ok(date) = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") else {
+                    bot.send_message(msg.chat.id, "Usage: /holiday_add <YYYY-MM-DD>")
+                        .await?;
+                    return Ok(());
+                };
+                add_holiday(&db, date).await?;
+                reschedule_routines(&db, Utc::now().with_timezone(&app_config.timezone)).await?;
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Routines won't play on {}.", date.format("%Y-%m-%d")),
+                )
+                .await?;
+            }
+            Command::RoomOrder => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let room_names: Vec<String> = LocatedRoom::all(&db)
+                    .await?
+                    .into_iter()
+                    .map(|room| room.name)
+                    .collect();
+                if room_names.is_empty() {
+                    bot.send_message(msg.chat.id, "No rooms defined.").await?;
+                    return Ok(());
+                }
+
+                let keyboard = room_order_keyboard(&room_names)?;
+                let keyboard_msg = bot
+                    .send_message(msg.chat.id, "Tap the arrows to move a room up or down.")
+                    .reply_markup(keyboard.build_inline_keyboard_markup())
+                    .await?;
+                keyboard.insert_into_db(&db, &keyboard_msg.id).await?;
             }
             Command::RoomDel => {
                 if !app_config.is_admin(&msg.chat.id.0) {
@@ -116,7 +591,9 @@ impl Command {
                     return Ok(());
                 }
 
-                let rooms = sqlx::query!("SELECT * FROM rooms").fetch_all(&db).await?;
+                let rooms = sqlx::query!("SELECT * FROM rooms ORDER BY sort_index, name")
+                    .fetch_all(&db)
+                    .await?;
                 if rooms.len() <= 0 {
                     bot.send_message(msg.chat.id, "No rooms defined.").await?;
                     return Ok(());
@@ -143,6 +620,71 @@ impl Command {
                     .await?;
                 keyboard.insert_into_db(&db, &keyboard_msg.id).await?;
             }
+            Command::RoomGroups => {
+                let groups = RoomGroup::all(&db).await?;
+                let text = match groups.is_empty() {
+                    true => "No room groups defined. Use /room_group_set to create one.".into(),
+                    false => {
+                        "Room groups:\n".to_owned()
+                            + &groups
+                                .iter()
+                                .map(|group| match group.preset {
+                                    Some(preset) => format!(
+                                        "{} ↦ {} ({})",
+                                        group.name,
+                                        preset,
+                                        group.rooms.join(", ")
+                                    ),
+                                    None => format!(
+                                        "{} ↦ {}, one after another",
+                                        group.name,
+                                        group.rooms.join(", ")
+                                    ),
+                                })
+                                .join("\n")
+                    }
+                };
+                bot.send_message(msg.chat.id, text).await?;
+            }
+            Command::RoomGroupSet => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                bot.send_message(
+                    msg.chat.id,
+                    "Please send me a name for the group, i. e. All floors. An existing group is replaced.",
+                )
+                .await?;
+                dialogue
+                    .update(dialogues::State::ReceiveRoomGroupName)
+                    .await?;
+            }
+            Command::RoomGroupDel => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let names = RoomGroup::names(&db).await?;
+                if names.is_empty() {
+                    bot.send_message(msg.chat.id, "No room groups defined.")
+                        .await?;
+                    return Ok(());
+                }
+                select_keyboard(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    names,
+                    "Select a group to delete.",
+                    |name| CallbackType::RoomGroupDel { name },
+                )
+                .await?;
+            }
             Command::GroupLink => {
                 if !app_config.is_admin(&msg.chat.id.0) {
                     bot.send_message(msg.chat.id, "Insufficient permission.")
@@ -163,6 +705,73 @@ impl Command {
                     )
                     .await?;
             }
+            Command::Storage => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let usage = retention::usage(&db, &app_config.audio_dir).await?;
+                let keyboard = InlineDataKeyboard::new().buttons(vec![
+                    InlineDataKeyboardButton {
+                        text: "Purge now".into(),
+                        data: serde_json::to_string(&CallbackType::StoragePurge { all: false })?,
+                    },
+                    InlineDataKeyboardButton {
+                        text: "Purge all".into(),
+                        data: serde_json::to_string(&CallbackType::StoragePurge { all: true })?,
+                    },
+                ]);
+                let keyboard_msg = bot
+                    .send_message(
+                        msg.chat.id,
+                        format!(
+                            "{} audios in {} files, using {}.\nRetention: {}.",
+                            usage.entries,
+                            usage.files,
+                            format_file_size(usage.bytes),
+                            RetentionPolicy::from(app_config.as_ref())
+                        ),
+                    )
+                    .reply_markup(keyboard.build_inline_keyboard_markup())
+                    .await?;
+                keyboard.insert_into_db(&db, &keyboard_msg.id).await?;
+            }
+            Command::ForgetMe => {
+                confirm_forget(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    msg.chat.id.0,
+                    "Delete all audios you sent me? This can't be undone.".into(),
+                )
+                .await?;
+            }
+            Command::PurgeUser(user_id) => {
+                if !app_config.is_admin(&msg.chat.id.0) {
+                    bot.send_message(msg.chat.id, "Insufficient permission.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let Ok(user_id) = user_id.trim().parse::<i64>() else {
+                    bot.send_message(msg.chat.id, "Usage: /purge_user <user_id>")
+                        .await?;
+                    return Ok(());
+                };
+                confirm_forget(
+                    &bot,
+                    &db,
+                    msg.chat.id,
+                    user_id,
+                    format!(
+                        "Delete all audios user {} sent? This can't be undone.",
+                        user_id
+                    ),
+                )
+                .await?;
+            }
         };
 
         Ok(())
@@ -1,9 +1,12 @@
-use std::{error::Error, fs::create_dir, io, path::PathBuf};
+use std::{collections::BTreeSet, error::Error, fs::create_dir, io, path::PathBuf};
 
+use chrono_tz::Tz;
 #[cfg(feature = "dotenvy")]
 use dotenvy::dotenv;
 use serde::Deserialize;
 
+use crate::audio::dsp::DspProfiles;
+
 fn ensure_dir(path: &PathBuf) -> std::io::Result<()> {
     match create_dir(&path) {
         Err(e) => match e.kind() {
@@ -18,6 +21,9 @@ pub struct AppConfig {
     pub env: EnvConfig,
     pub audio_dir: PathBuf,
     pub db_file: PathBuf,
+    pub dsp_profiles: DspProfiles,
+    pub timezone: Tz,
+    pub combined_presets: Vec<CombinedPreset>,
 }
 
 impl AppConfig {
@@ -30,16 +36,82 @@ impl AppConfig {
         ensure_dir(&data_dir)?;
         ensure_dir(&audio_dir)?;
 
+        let dsp_profiles = match &env.dsp_profiles_file {
+            Some(path) => DspProfiles::load(&PathBuf::from(path))?,
+            None => DspProfiles::default(),
+        };
+
+        let timezone = env
+            .timezone
+            .parse::<Tz>()
+            .map_err(|err| format!("invalid timezone: {}", err))?;
+
+        let combined_presets = env
+            .combined_presets
+            .iter()
+            .map(|combined_preset| combined_preset.parse())
+            .collect::<Result<_, _>>()?;
+
         Ok(AppConfig {
             env,
             audio_dir,
             db_file,
+            dsp_profiles,
+            timezone,
+            combined_presets,
         })
     }
 
     pub fn is_admin(&self, id: &i64) -> bool {
         self.env.admin_users.contains(id)
     }
+
+    pub fn limits(&self, id: &i64) -> Limits {
+        if self.is_admin(id) {
+            Limits {
+                max_duration: self.env.admin_max_duration,
+                max_file_size: self.env.admin_max_file_size,
+            }
+        } else {
+            Limits {
+                max_duration: self.env.max_duration,
+                max_file_size: self.env.max_file_size,
+            }
+        }
+    }
+}
+
+/// A mixer preset routing to several rooms at once, i. e. `Hall+Yard=12`.
+pub struct CombinedPreset {
+    pub rooms: BTreeSet<String>,
+    pub preset: u16,
+}
+
+impl std::str::FromStr for CombinedPreset {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid combined preset: {}", text);
+        let (rooms, preset) = text.rsplit_once('=').ok_or_else(invalid)?;
+        let rooms: BTreeSet<String> = rooms
+            .split('+')
+            .map(|room| room.trim().to_owned())
+            .filter(|room| !room.is_empty())
+            .collect();
+        if rooms.len() < 2 {
+            return Err(invalid());
+        }
+        Ok(CombinedPreset {
+            rooms,
+            preset: preset.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Limits for announcements in seconds and bytes, `None` means unlimited.
+pub struct Limits {
+    pub max_duration: Option<u64>,
+    pub max_file_size: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -52,6 +124,10 @@ pub struct EnvConfig {
     pub player_command: String,
     #[serde(default = "default_player_start_delay")]
     pub player_start_delay: u64,
+    #[serde(default = "default_player_volume")]
+    pub player_volume: u32,
+    #[serde(default = "default_arming_window")]
+    pub arming_window: u64,
     #[serde(default = "default_data_dir")]
     pub data_dir: String,
     pub heartbeat_endpoint: Option<String>,
@@ -59,6 +135,36 @@ pub struct EnvConfig {
     pub heartbeat_interval: u64,
     #[serde(default = "default_mock_ahm_connection")]
     pub mock_ahm_connection: bool,
+    #[serde(default = "default_progress_interval")]
+    pub progress_interval: u64,
+    pub loudness_target: Option<f64>,
+    #[serde(default = "default_loudness_true_peak")]
+    pub loudness_true_peak: f64,
+    pub decoder_command: Option<String>,
+    pub dsp_profiles_file: Option<String>,
+    pub silence_threshold: Option<f64>,
+    #[serde(default = "default_silence_padding")]
+    pub silence_padding: u64,
+    pub max_duration: Option<u64>,
+    pub max_file_size: Option<u64>,
+    pub admin_max_duration: Option<u64>,
+    pub admin_max_file_size: Option<u64>,
+    pub audio_retention_days: Option<u64>,
+    pub audio_max_age_days: Option<u64>,
+    pub audio_max_total_size: Option<u64>,
+    #[serde(default = "default_audio_cleanup_interval")]
+    pub audio_cleanup_interval: u64,
+    pub tts_command: Option<String>,
+    #[serde(default = "default_tts_voice")]
+    pub tts_voice: String,
+    #[serde(default)]
+    pub tts_voices: Vec<String>,
+    #[serde(default = "default_tts_max_length")]
+    pub tts_max_length: usize,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub combined_presets: Vec<String>,
 }
 
 fn default_ahm_port() -> u16 {
@@ -69,6 +175,14 @@ fn default_player_start_delay() -> u64 {
     0
 }
 
+fn default_player_volume() -> u32 {
+    100
+}
+
+fn default_arming_window() -> u64 {
+    0
+}
+
 fn default_data_dir() -> String {
     "./data".into()
 }
@@ -81,6 +195,34 @@ fn default_mock_ahm_connection() -> bool {
     false
 }
 
+fn default_progress_interval() -> u64 {
+    5000
+}
+
+fn default_loudness_true_peak() -> f64 {
+    -1.0
+}
+
+fn default_silence_padding() -> u64 {
+    150
+}
+
+fn default_audio_cleanup_interval() -> u64 {
+    3600000
+}
+
+fn default_tts_voice() -> String {
+    "en".into()
+}
+
+fn default_tts_max_length() -> usize {
+    500
+}
+
+fn default_timezone() -> String {
+    "UTC".into()
+}
+
 impl EnvConfig {
     pub fn from_dotenv() -> Result<Self, Box<dyn Error>> {
         #[cfg(feature = "dotenvy")]
@@ -100,3 +242,29 @@ impl EnvConfig {
         return Ok(envy::from_env::<EnvConfig>()?);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_combined_presets() {
+        let combined: CombinedPreset = " Hall + Yard+Office = 12".parse().unwrap();
+        assert_eq!(
+            combined.rooms.into_iter().collect::<Vec<_>>(),
+            ["Hall", "Office", "Yard"]
+        );
+        assert_eq!(combined.preset, 12);
+
+        for invalid in [
+            "Hall+Yard",
+            "Hall=12",
+            "Hall+=12",
+            "Hall+Yard=",
+            "Hall+Yard=x",
+            "Hall+Yard=70000",
+        ] {
+            assert!(invalid.parse::<CombinedPreset>().is_err(), "{}", invalid);
+        }
+    }
+}
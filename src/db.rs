@@ -32,3 +32,15 @@ pub async fn init(app_config: &AppConfig) -> Pool<Sqlite> {
 
     db
 }
+
+/// An empty database with all tables, for tests.
+#[cfg(test)]
+pub async fn memory() -> Pool<Sqlite> {
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    migrate!("./migrations").run(&db).await.unwrap();
+    db
+}
@@ -1 +1,161 @@
+pub mod rename_clip;
+pub mod room_group;
+pub mod routine;
+pub mod schedule;
 pub mod set_room;
+pub mod template;
+
+use std::{error::Error, process::exit, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    dispatching::{
+        dialogue::{serializer::Json, ErasedStorage, SqliteStorage, Storage},
+        DpHandlerDescription, HandlerExt,
+    },
+    dptree::{self, Handler},
+    payloads::SendMessageSetters,
+    prelude::{DependencyMap, Dialogue},
+    requests::Requester,
+    types::{KeyboardButton, KeyboardMarkup, Message},
+    Bot,
+};
+
+use crate::config::AppConfig;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub enum State {
+    #[default]
+    Inactive,
+    ReceiveRoomName,
+    ReceivePresetNumber {
+        name: String,
+    },
+    ReceiveStartDelay {
+        name: String,
+    },
+    ReceivePlayerCommand {
+        name: String,
+    },
+    ReceiveVolume {
+        name: String,
+    },
+    ReceiveVoice {
+        name: String,
+    },
+    ReceiveRoomLocation {
+        name: String,
+    },
+    ReceiveTemplateName,
+    ReceiveTemplateText {
+        name: String,
+    },
+    ReceiveTemplateValue {
+        name: String,
+        values: Vec<String>,
+    },
+    ReceiveClipName {
+        name: String,
+    },
+    ReceiveScheduleRoom {
+        unique_id: String,
+    },
+    ReceiveScheduleTime {
+        unique_id: String,
+        room_name: String,
+    },
+    ReceiveRoomGroupName,
+    ReceiveRoomGroupRooms {
+        name: String,
+        rooms: Vec<String>,
+    },
+    ReceiveRoomGroupPreset {
+        name: String,
+        rooms: Vec<String>,
+    },
+    ReceiveRoutineName,
+    ReceiveRoutineClip {
+        name: String,
+    },
+    ReceiveRoutineRooms {
+        name: String,
+        clip_name: String,
+        rooms: Vec<String>,
+    },
+    ReceiveRoutineRule {
+        name: String,
+        clip_name: String,
+        rooms: Vec<String>,
+    },
+}
+
+/// All dialogues share one storage, as it keeps a single state per chat.
+pub type DialogueStorage = Arc<ErasedStorage<State>>;
+
+pub type DialogueDependency = Dialogue<State, ErasedStorage<State>>;
+
+pub async fn open_storage(app_config: &AppConfig) -> DialogueStorage {
+    let db_file = &app_config.db_file.to_str().unwrap_or_else(|| {
+        log::error!("invalid db file path: {:?}", app_config.db_file);
+        exit(1)
+    });
+    SqliteStorage::open(db_file, Json)
+        .await
+        .unwrap_or_else(|e| {
+            log::error!("db connection for storage failed: {}", e);
+            exit(1)
+        })
+        .erase()
+}
+
+/// Ends picking several entries from a reply keyboard.
+const DONE: &str = "Done";
+
+/// A one-time keyboard with a button per name, replacing the text input.
+fn reply_keyboard(names: impl IntoIterator<Item = String>) -> KeyboardMarkup {
+    let names: Vec<String> = names.into_iter().collect();
+    let buttons = names
+        .chunks(3)
+        .map(|row| row.iter().map(KeyboardButton::new).collect::<Vec<_>>());
+    KeyboardMarkup::new(buttons)
+        .one_time_keyboard()
+        .resize_keyboard()
+}
+
+/// Offers the rooms and [DONE] to pick several of them one by one.
+async fn ask_for_rooms(
+    bot: &Bot,
+    msg: &Message,
+    db: &Pool<Sqlite>,
+    text: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let room_names = sqlx::query!("SELECT name FROM rooms ORDER BY sort_index, name")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|room| room.name);
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(reply_keyboard(room_names.chain([DONE.to_owned()])))
+        .await?;
+    Ok(())
+}
+
+pub fn make_inject_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    dptree::entry().enter_dialogue::<Message, ErasedStorage<State>, State>()
+}
+
+pub fn make_endpoint_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    // the room dialogue resets the state of users who aren't admins, so it has to come last
+    dptree::entry()
+        .branch(template::make_endpoint_handler())
+        .branch(rename_clip::make_endpoint_handler())
+        .branch(schedule::make_endpoint_handler())
+        .branch(routine::make_endpoint_handler())
+        .branch(room_group::make_endpoint_handler())
+        .branch(set_room::make_endpoint_handler())
+}
@@ -0,0 +1,64 @@
+use std::{error::Error, sync::Arc};
+
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    dptree::{self, Handler},
+    prelude::DependencyMap,
+    requests::Requester,
+    types::Message,
+    Bot,
+};
+
+use super::{DialogueDependency, State};
+use crate::{clip::Clip, config::AppConfig};
+
+pub fn make_endpoint_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    dptree::case![State::ReceiveClipName { name }].endpoint(
+        |bot: Bot,
+         msg: Message,
+         db: Pool<Sqlite>,
+         app_config: Arc<AppConfig>,
+         dialogue: DialogueDependency,
+         name: String| async move {
+            if !app_config.is_admin(&msg.chat.id.0) {
+                dialogue.reset().await?;
+                bot.send_message(msg.chat.id, "Insufficient permission.")
+                    .await?;
+                return Ok(());
+            }
+
+            let new_name = msg.text().map(str::trim).unwrap_or_default();
+            if new_name.is_empty() {
+                bot.send_message(msg.chat.id, "Please send me the new name of the clip.")
+                    .await?;
+                return Ok(());
+            }
+
+            if !Clip::rename(&db, &name, new_name).await? {
+                let text = match Clip::find(&db, &name).await? {
+                    Some(_) => format!(
+                        "A clip named {} already exists, please send me another name.",
+                        new_name
+                    ),
+                    None => {
+                        dialogue.reset().await?;
+                        format!("The clip {} no longer exists.", name)
+                    }
+                };
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+
+            bot.send_message(
+                msg.chat.id,
+                format!("Renamed clip {} to {}.", name, new_name),
+            )
+            .await?;
+            dialogue.reset().await?;
+            Ok(())
+        },
+    )
+}
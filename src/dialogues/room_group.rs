@@ -0,0 +1,145 @@
+use std::error::Error;
+
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    dptree::{self, Handler},
+    payloads::SendMessageSetters,
+    prelude::DependencyMap,
+    requests::Requester,
+    types::{KeyboardRemove, Message},
+    Bot,
+};
+
+use super::{ask_for_rooms, DialogueDependency, State, DONE};
+use crate::{announcement::Room, room_group::RoomGroup};
+
+pub fn make_endpoint_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    dptree::entry()
+        .branch(dptree::case![State::ReceiveRoomGroupName].endpoint(
+            |bot: Bot, msg: Message, db: Pool<Sqlite>, dialogue: DialogueDependency| async move {
+                let name = msg.text().map(str::trim).unwrap_or_default();
+                if name.is_empty() {
+                    bot.send_message(msg.chat.id, "Please send me a name for the group.")
+                        .await?;
+                    return Ok(());
+                }
+
+                ask_for_rooms(
+                    &bot,
+                    &msg,
+                    &db,
+                    "Which rooms belong to the group? Pick them one by one and press Done.",
+                )
+                .await?;
+                dialogue
+                    .update(State::ReceiveRoomGroupRooms {
+                        name: name.to_owned(),
+                        rooms: Vec::new(),
+                    })
+                    .await?;
+                Ok(())
+            },
+        ))
+        .branch(
+            dptree::case![State::ReceiveRoomGroupRooms { name, rooms }].endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 dialogue: DialogueDependency,
+                 (name, mut rooms): (String, Vec<String>)| async move {
+                    let room_name = msg.text().map(str::trim).unwrap_or_default();
+                    if room_name == DONE {
+                        if rooms.len() < 2 {
+                            ask_for_rooms(&bot, &msg, &db, "Please pick at least two rooms.")
+                                .await?;
+                            return Ok(());
+                        }
+
+                        bot.send_message(
+                            msg.chat.id,
+                            "Send me the preset routing to all of these rooms, or - to play in one room after another.",
+                        )
+                        .reply_markup(KeyboardRemove::new())
+                        .await?;
+                        dialogue
+                            .update(State::ReceiveRoomGroupPreset { name, rooms })
+                            .await?;
+                        return Ok(());
+                    }
+
+                    if Room::find(&db, room_name).await?.is_none() {
+                        ask_for_rooms(&bot, &msg, &db, "Please pick one of the rooms or Done.")
+                            .await?;
+                        return Ok(());
+                    }
+                    if !rooms.iter().any(|room| room == room_name) {
+                        rooms.push(room_name.to_owned());
+                    }
+                    ask_for_rooms(
+                        &bot,
+                        &msg,
+                        &db,
+                        &format!(
+                            "Rooms: {}. Pick another room or press Done.",
+                            rooms.join(", ")
+                        ),
+                    )
+                    .await?;
+                    dialogue
+                        .update(State::ReceiveRoomGroupRooms { name, rooms })
+                        .await?;
+                    Ok(())
+                },
+            ),
+        )
+        .branch(
+            dptree::case![State::ReceiveRoomGroupPreset { name, rooms }].endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 dialogue: DialogueDependency,
+                 (name, rooms): (String, Vec<String>)| async move {
+                    let preset = match msg.text().map(str::trim) {
+                        Some("-") => None,
+                        text => match text.and_then(|text| text.parse::<i64>().ok()) {
+                            Some(preset) if (1..=500).contains(&preset) => Some(preset),
+                            _ => {
+                                bot.send_message(
+                                    msg.chat.id,
+                                    "The preset should be a number between 1 and 500, or - for none.",
+                                )
+                                .await?;
+                                return Ok(());
+                            }
+                        },
+                    };
+
+                    let group = RoomGroup {
+                        name,
+                        preset,
+                        rooms,
+                    };
+                    group.save(&db).await?;
+                    let routing = match group.preset {
+                        Some(preset) => format!("with preset {}", preset),
+                        None => "in one room after another".into(),
+                    };
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Saved group {} ({}), played {}.",
+                            group.name,
+                            group.rooms.join(", "),
+                            routing
+                        ),
+                    )
+                    .await?;
+                    dialogue.reset().await?;
+                    Ok(())
+                },
+            ),
+        )
+}
@@ -0,0 +1,212 @@
+use std::{error::Error, sync::Arc};
+
+use chrono::Utc;
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    dptree::{self, Handler},
+    payloads::SendMessageSetters,
+    prelude::DependencyMap,
+    requests::Requester,
+    types::{KeyboardRemove, Message},
+    Bot,
+};
+
+use super::{ask_for_rooms, reply_keyboard, DialogueDependency, State, DONE};
+use crate::{
+    announcement::Room,
+    clip::Clip,
+    config::AppConfig,
+    routine::{holidays, Routine, Rule},
+    schedule::format_time,
+};
+
+pub fn make_endpoint_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    dptree::entry()
+        .branch(dptree::case![State::ReceiveRoutineName].endpoint(
+            |bot: Bot, msg: Message, db: Pool<Sqlite>, dialogue: DialogueDependency| async move {
+                let name = msg.text().map(str::trim).unwrap_or_default();
+                if name.is_empty() {
+                    bot.send_message(msg.chat.id, "Please send me a name for the routine.")
+                        .await?;
+                    return Ok(());
+                }
+
+                let clip_names = Clip::all(&db).await?.into_iter().map(|clip| clip.name);
+                bot.send_message(msg.chat.id, "Which clip should it play?")
+                    .reply_markup(reply_keyboard(clip_names))
+                    .await?;
+                dialogue
+                    .update(State::ReceiveRoutineClip {
+                        name: name.to_owned(),
+                    })
+                    .await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceiveRoutineClip { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let clip_name = msg.text().map(str::trim).unwrap_or_default();
+                if Clip::find(&db, clip_name).await?.is_none() {
+                    bot.send_message(msg.chat.id, "Please pick one of the clips.")
+                        .await?;
+                    return Ok(());
+                }
+
+                ask_for_rooms(
+                    &bot,
+                    &msg,
+                    &db,
+                    "In which rooms should it play? Pick them one by one and press Done.",
+                )
+                .await?;
+                dialogue
+                    .update(State::ReceiveRoutineRooms {
+                        name,
+                        clip_name: clip_name.to_owned(),
+                        rooms: Vec::new(),
+                    })
+                    .await?;
+                Ok(())
+            },
+        ))
+        .branch(
+            dptree::case![State::ReceiveRoutineRooms {
+                name,
+                clip_name,
+                rooms
+            }]
+            .endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 dialogue: DialogueDependency,
+                 (name, clip_name, mut rooms): (String, String, Vec<String>)| async move {
+                    let room_name = msg.text().map(str::trim).unwrap_or_default();
+                    if room_name == DONE {
+                        if rooms.is_empty() {
+                            ask_for_rooms(&bot, &msg, &db, "Please pick at least one room.")
+                                .await?;
+                            return Ok(());
+                        }
+
+                        bot.send_message(
+                            msg.chat.id,
+                            "When should it play? Send me days and times like Mon-Fri 08:00,12:30, weekends 10:00 or daily 07:45.",
+                        )
+                        .reply_markup(KeyboardRemove::new())
+                        .await?;
+                        dialogue
+                            .update(State::ReceiveRoutineRule {
+                                name,
+                                clip_name,
+                                rooms,
+                            })
+                            .await?;
+                        return Ok(());
+                    }
+
+                    if Room::find(&db, room_name).await?.is_none() {
+                        ask_for_rooms(&bot, &msg, &db, "Please pick one of the rooms or Done.")
+                            .await?;
+                        return Ok(());
+                    }
+                    if !rooms.iter().any(|room| room == room_name) {
+                        rooms.push(room_name.to_owned());
+                    }
+                    ask_for_rooms(
+                        &bot,
+                        &msg,
+                        &db,
+                        &format!(
+                            "Rooms: {}. Pick another room or press Done.",
+                            rooms.join(", ")
+                        ),
+                    )
+                    .await?;
+                    dialogue
+                        .update(State::ReceiveRoutineRooms {
+                            name,
+                            clip_name,
+                            rooms,
+                        })
+                        .await?;
+                    Ok(())
+                },
+            ),
+        )
+        .branch(
+            dptree::case![State::ReceiveRoutineRule {
+                name,
+                clip_name,
+                rooms
+            }]
+            .endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 app_config: Arc<AppConfig>,
+                 dialogue: DialogueDependency,
+                 (name, clip_name, rooms): (String, String, Vec<String>)| async move {
+                    let text = msg
+                        .text()
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let rule = match text.parse::<Rule>() {
+                        Ok(rule) => rule,
+                        Err(err) => {
+                            bot.send_message(msg.chat.id, format!("Invalid rule, {}.", err))
+                                .await?;
+                            return Ok(());
+                        }
+                    };
+                    let now = Utc::now().with_timezone(&app_config.timezone);
+                    let Some(next_run) = rule.next_after(now, &holidays(&db).await?) else {
+                        bot.send_message(
+                            msg.chat.id,
+                            "This rule never matches, please send me another one.",
+                        )
+                        .await?;
+                        return Ok(());
+                    };
+
+                    let next_run_at = next_run.timestamp();
+                    let inserted = Routine::insert(
+                        &db,
+                        &name,
+                        &clip_name,
+                        &rooms,
+                        &text,
+                        Some(next_run_at),
+                        msg.chat.id.0,
+                    )
+                    .await?;
+                    let text = match inserted {
+                        true => format!(
+                            "Saved routine {}, playing {} in: {}. The first run is at {} ({}).",
+                            name,
+                            clip_name,
+                            rooms.join(", "),
+                            format_time(next_run_at, app_config.timezone),
+                            app_config.timezone
+                        ),
+                        false => format!(
+                            "A routine named {} already exists, use /routines to delete it first.",
+                            name
+                        ),
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                    dialogue.reset().await?;
+                    Ok(())
+                },
+            ),
+        )
+}
@@ -0,0 +1,99 @@
+use std::{error::Error, sync::Arc};
+
+use chrono::Utc;
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    dptree::{self, Handler},
+    payloads::SendMessageSetters,
+    prelude::DependencyMap,
+    requests::Requester,
+    types::{KeyboardRemove, Message},
+    Bot,
+};
+
+use super::{DialogueDependency, State};
+use crate::{
+    announcement::Room,
+    config::AppConfig,
+    schedule::{format_time, parse_time, Schedule},
+};
+
+pub fn make_endpoint_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    dptree::entry()
+        .branch(
+            dptree::case![State::ReceiveScheduleRoom { unique_id }].endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 app_config: Arc<AppConfig>,
+                 dialogue: DialogueDependency,
+                 unique_id: String| async move {
+                    let room_name = msg.text().map(str::trim).unwrap_or_default();
+                    if Room::find(&db, room_name).await?.is_none() {
+                        bot.send_message(msg.chat.id, "Please pick one of the rooms.")
+                            .await?;
+                        return Ok(());
+                    }
+
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "When should I play it? Send me a time like 14:00 or 2024-05-01 14:00 ({}).",
+                            app_config.timezone
+                        ),
+                    )
+                    .reply_markup(KeyboardRemove::new())
+                    .await?;
+                    dialogue
+                        .update(State::ReceiveScheduleTime {
+                            unique_id,
+                            room_name: room_name.to_owned(),
+                        })
+                        .await?;
+                    Ok(())
+                },
+            ),
+        )
+        .branch(
+            dptree::case![State::ReceiveScheduleTime {
+                unique_id,
+                room_name
+            }]
+            .endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 app_config: Arc<AppConfig>,
+                 dialogue: DialogueDependency,
+                 (unique_id, room_name): (String, String)| async move {
+                    let now = Utc::now().with_timezone(&app_config.timezone);
+                    let Some(run_at) = msg.text().and_then(|text| parse_time(text, now)) else {
+                        bot.send_message(
+                            msg.chat.id,
+                            "Please send me a future time like 14:00 or 2024-05-01 14:00.",
+                        )
+                        .await?;
+                        return Ok(());
+                    };
+
+                    let run_at = run_at.timestamp();
+                    Schedule::insert(&db, &unique_id, &room_name, run_at, msg.chat.id.0).await?;
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Scheduled the audio in: {} at {} ({}). Use /scheduled to cancel it.",
+                            room_name,
+                            format_time(run_at, app_config.timezone),
+                            app_config.timezone
+                        ),
+                    )
+                    .await?;
+                    dialogue.reset().await?;
+                    Ok(())
+                },
+            ),
+        )
+}
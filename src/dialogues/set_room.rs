@@ -1,52 +1,29 @@
-use std::{error::Error, process::exit, sync::Arc};
+use std::{error::Error, str::FromStr, sync::Arc};
 
-use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite};
 use teloxide::{
-    dispatching::{
-        dialogue::{serializer::Json, ErasedStorage, SqliteStorage, Storage},
-        DpHandlerDescription, HandlerExt,
-    },
+    dispatching::DpHandlerDescription,
     dptree::{self, Handler},
-    prelude::{DependencyMap, Dialogue},
+    prelude::DependencyMap,
     requests::Requester,
     types::Message,
     Bot,
 };
 
-use crate::config::AppConfig;
-
-#[derive(Clone, Default, Serialize, Deserialize)]
-pub enum State {
-    #[default]
-    Inactive,
-    ReceiveRoomName,
-    ReceivePresetNumber {
-        name: String,
-    },
-}
-
-type MyStorage = std::sync::Arc<ErasedStorage<State>>;
+use super::{DialogueDependency, State};
+use crate::{
+    config::AppConfig,
+    room_tree::{format_location, parse_location},
+};
 
-pub type DialogueDependency = Dialogue<State, ErasedStorage<State>>;
+const LOCATION_QUESTION: &str = "Now send me where the room is, with the levels separated by / like Main site/Building A/Floor 2, or - to list it on the top level.";
 
-pub async fn make_inject_handler(
-    app_config: &AppConfig,
-) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
-{
-    let db_file = &app_config.db_file.to_str().unwrap_or_else(|| {
-        log::error!("invalid db file path: {:?}", app_config.db_file);
-        exit(1)
-    });
-    let storage: MyStorage = SqliteStorage::open(db_file, Json)
-        .await
-        .unwrap_or_else(|e| {
-            log::error!("db connection for storage failed: {}", e);
-            exit(1)
-        })
-        .erase();
-
-    dptree::map(move || storage.clone()).enter_dialogue::<Message, ErasedStorage<State>, State>()
+/// Parses a room setting, `-` falls back to the default.
+fn parse_setting<T: FromStr>(text: &str) -> Result<Option<T>, T::Err> {
+    match text.trim() {
+        "-" => Ok(None),
+        text => text.parse().map(Some),
+    }
 }
 
 pub fn make_endpoint_handler(
@@ -97,6 +74,7 @@ pub fn make_endpoint_handler(
             |bot: Bot,
              msg: Message,
              db: Pool<Sqlite>,
+             app_config: Arc<AppConfig>,
              dialogue: DialogueDependency,
              name: String| async move {
                 let text = match msg.text() {
@@ -127,7 +105,8 @@ pub fn make_endpoint_handler(
                 }
 
                 let res = sqlx::query!(
-                    "INSERT INTO rooms VALUES($1, $2)
+                    "INSERT INTO rooms (name, preset, sort_index)
+                        VALUES($1, $2, (SELECT COALESCE(MAX(sort_index) + 1, 0) FROM rooms))
                         ON CONFLICT(name) DO UPDATE SET preset=$2",
                     name,
                     preset
@@ -142,10 +121,207 @@ pub fn make_endpoint_handler(
 
                 bot.send_message(
                     msg.chat.id,
-                    format!("Linked room {} to preset {}.", name, preset),
+                    format!(
+                        "Linked room {} to preset {}.\n\nNow send me how many milliseconds to wait after switching the preset, or - to use the default ({} ms).",
+                        name, preset, app_config.env.player_start_delay
+                    ),
+                )
+                .await?;
+
+                dialogue.update(State::ReceiveStartDelay { name }).await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceiveStartDelay { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let start_delay = match msg.text().map(parse_setting::<i64>) {
+                    Some(Ok(start_delay)) if start_delay.unwrap_or(0) >= 0 => start_delay,
+                    _ => {
+                        bot.send_message(
+                            msg.chat.id,
+                            "Please send me a number of milliseconds or -.",
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
+
+                sqlx::query!(
+                    "UPDATE rooms SET start_delay = ? WHERE name = ?",
+                    start_delay,
+                    name
+                )
+                .execute(&db)
+                .await?;
+
+                bot.send_message(
+                    msg.chat.id,
+                    "Now send me the player command for this room, or - to use the default. %f is replaced by the file and %v by the volume.",
                 )
                 .await?;
+                dialogue.update(State::ReceivePlayerCommand { name }).await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceivePlayerCommand { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             app_config: Arc<AppConfig>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let player_command = match msg.text().map(str::trim) {
+                    Some("-") => None,
+                    Some(text) if shell_words::split(text).is_ok_and(|args| !args.is_empty()) => {
+                        Some(text)
+                    }
+                    _ => {
+                        bot.send_message(
+                            msg.chat.id,
+                            "Please send me a valid command line or -.",
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
+
+                sqlx::query!(
+                    "UPDATE rooms SET player_command = ? WHERE name = ?",
+                    player_command,
+                    name
+                )
+                .execute(&db)
+                .await?;
 
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Now send me the volume in percent (0 to 200), or - to use the default ({}%).",
+                        app_config.env.player_volume
+                    ),
+                )
+                .await?;
+                dialogue.update(State::ReceiveVolume { name }).await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceiveVolume { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             app_config: Arc<AppConfig>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let volume = match msg.text().map(parse_setting::<i64>) {
+                    Some(Ok(volume)) if (0..=200).contains(&volume.unwrap_or(0)) => volume,
+                    _ => {
+                        bot.send_message(
+                            msg.chat.id,
+                            "Please send me a volume between 0 and 200 or -.",
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
+
+                sqlx::query!("UPDATE rooms SET volume = ? WHERE name = ?", volume, name)
+                    .execute(&db)
+                    .await?;
+
+                if app_config.env.tts_command.is_none() {
+                    bot.send_message(msg.chat.id, LOCATION_QUESTION).await?;
+                    dialogue.update(State::ReceiveRoomLocation { name }).await?;
+                    return Ok(());
+                }
+
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Now send me the voice to read out texts in this room, or - to use the default ({}).",
+                        app_config.env.tts_voice
+                    ),
+                )
+                .await?;
+                dialogue.update(State::ReceiveVoice { name }).await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceiveVoice { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             app_config: Arc<AppConfig>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let voices = &app_config.env.tts_voices;
+                let tts_voice = match msg.text().map(str::trim) {
+                    Some("-") => None,
+                    Some(voice)
+                        if !voice.is_empty()
+                            && (voices.is_empty() || voices.iter().any(|known| known == voice)) =>
+                    {
+                        Some(voice)
+                    }
+                    _ if !voices.is_empty() => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("Please send me one of the voices {} or -.", voices.join(", ")),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                    _ => {
+                        bot.send_message(msg.chat.id, "Please send me a voice or -.")
+                            .await?;
+                        return Ok(());
+                    }
+                };
+
+                sqlx::query!(
+                    "UPDATE rooms SET tts_voice = ? WHERE name = ?",
+                    tts_voice,
+                    name
+                )
+                .execute(&db)
+                .await?;
+
+                bot.send_message(msg.chat.id, LOCATION_QUESTION).await?;
+                dialogue.update(State::ReceiveRoomLocation { name }).await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceiveRoomLocation { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let location = match msg.text().map(str::trim) {
+                    Some("-") => None,
+                    Some(text) if !parse_location(text).is_empty() => {
+                        Some(format_location(&parse_location(text)))
+                    }
+                    _ => {
+                        bot.send_message(msg.chat.id, "Please send me a location or -.")
+                            .await?;
+                        return Ok(());
+                    }
+                };
+
+                sqlx::query!(
+                    "UPDATE rooms SET location = ? WHERE name = ?",
+                    location,
+                    name
+                )
+                .execute(&db)
+                .await?;
+
+                bot.send_message(msg.chat.id, format!("Saved the settings of room {}.", name))
+                    .await?;
                 dialogue.reset().await?;
                 Ok(())
             },
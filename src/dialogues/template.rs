@@ -0,0 +1,157 @@
+use std::{error::Error, sync::Arc};
+
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    dispatching::DpHandlerDescription,
+    dptree::{self, Handler},
+    prelude::DependencyMap,
+    requests::Requester,
+    types::Message,
+    Bot,
+};
+
+use super::{DialogueDependency, State};
+use crate::{
+    audio::processor::AudioProcessor, config::AppConfig, handle_text_message::handle_text_message,
+    template::Template,
+};
+
+/// Asks for the next missing value, or reads out the filled in template once all are known.
+pub async fn continue_template(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    audio_processor: &Arc<AudioProcessor>,
+    dialogue: &DialogueDependency,
+    template: &Template,
+    values: Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let chat_id = dialogue.chat_id();
+    match template.variables().get(values.len()) {
+        Some(variable) => {
+            bot.send_message(chat_id, format!("Please send me the {}.", variable))
+                .await?;
+            dialogue
+                .update(State::ReceiveTemplateValue {
+                    name: template.name.to_owned(),
+                    values,
+                })
+                .await?;
+        }
+        None => {
+            dialogue.reset().await?;
+            let text = template.render(&values);
+            handle_text_message(bot, db, app_config, audio_processor, chat_id, &text).await?;
+        }
+    }
+    Ok(())
+}
+
+pub fn make_endpoint_handler(
+) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
+{
+    dptree::entry()
+        .branch(dptree::case![State::ReceiveTemplateName].endpoint(
+            |bot: Bot, msg: Message, dialogue: DialogueDependency| async move {
+                let name = msg.text().map(str::trim).unwrap_or_default();
+                if name.is_empty() {
+                    bot.send_message(msg.chat.id, "Please send me a name for the template.")
+                        .await?;
+                    return Ok(());
+                }
+
+                bot.send_message(
+                    msg.chat.id,
+                    "Now send me the text to read out. Put variables in braces, i. e. The meeting in {room} starts in {minutes} minutes.",
+                )
+                .await?;
+                dialogue
+                    .update(State::ReceiveTemplateText {
+                        name: name.to_owned(),
+                    })
+                    .await?;
+                Ok(())
+            },
+        ))
+        .branch(dptree::case![State::ReceiveTemplateText { name }].endpoint(
+            |bot: Bot,
+             msg: Message,
+             db: Pool<Sqlite>,
+             dialogue: DialogueDependency,
+             name: String| async move {
+                let text = msg.text().map(str::trim).unwrap_or_default();
+                if text.is_empty() {
+                    bot.send_message(msg.chat.id, "Please send me the text of the template.")
+                        .await?;
+                    return Ok(());
+                }
+
+                sqlx::query!(
+                    "INSERT INTO templates (name, text) VALUES ($1, $2)
+                        ON CONFLICT(name) DO UPDATE SET text = $2",
+                    name,
+                    text
+                )
+                .execute(&db)
+                .await?;
+
+                let template = Template {
+                    name,
+                    text: text.to_owned(),
+                };
+                let variables = template.variables();
+                let text = match variables.is_empty() {
+                    true => format!("Saved template {} without variables.", template.name),
+                    false => format!(
+                        "Saved template {} with the variables {}.",
+                        template.name,
+                        variables.join(", ")
+                    ),
+                };
+                bot.send_message(msg.chat.id, text).await?;
+                dialogue.reset().await?;
+                Ok(())
+            },
+        ))
+        .branch(
+            dptree::case![State::ReceiveTemplateValue { name, values }].endpoint(
+                |bot: Bot,
+                 msg: Message,
+                 db: Pool<Sqlite>,
+                 app_config: Arc<AppConfig>,
+                 audio_processor: Arc<AudioProcessor>,
+                 dialogue: DialogueDependency,
+                 (name, mut values): (String, Vec<String>)| async move {
+                    let Some(template) = Template::find(&db, &name).await? else {
+                        bot.send_message(msg.chat.id, "This template no longer exists.")
+                            .await?;
+                        dialogue.reset().await?;
+                        return Ok(());
+                    };
+
+                    let value = msg.text().map(str::trim).unwrap_or_default();
+                    if value.is_empty() {
+                        let variable = template.variables().get(values.len()).copied();
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("Please send me the {} as text.", variable.unwrap_or("value")),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                    values.push(value.to_owned());
+
+                    continue_template(
+                        &bot,
+                        &db,
+                        &app_config,
+                        &audio_processor,
+                        &dialogue,
+                        &template,
+                        values,
+                    )
+                    .await
+                },
+            ),
+        )
+}
@@ -0,0 +1,257 @@
+use std::error::Error;
+
+use chrono::Utc;
+use itertools::Itertools;
+use sqlx::{Pool, QueryBuilder, Sqlite};
+use teloxide::{
+    net::Download,
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, Document, MessageId},
+    Bot,
+};
+
+use crate::{
+    announcement::Room,
+    calendar::{parse_calendar, SkipReason, SkippedEvent},
+    callback_handler::CallbackType,
+    clip::Clip,
+    config::AppConfig,
+    inline_data_keyboard::{InlineDataKeyboard, InlineDataKeyboardButton},
+    schedule::format_time,
+};
+
+/// Calendar files are read into memory, so they are limited way below the audio limit.
+const MAX_CALENDAR_SIZE: u32 = 1024 * 1024;
+
+/// Keeps a single import from flooding the schedule.
+const MAX_IMPORTED_JOBS: usize = 500;
+
+/// Keeps the preview within the message length limit.
+const MAX_PREVIEW_LINES: usize = 25;
+const MAX_LINE_LENGTH: usize = 120;
+
+/// Telegram rejects longer messages.
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// A scheduled announcement waiting for the confirmation of an import. The clip and room are
+/// looked up again once it is confirmed, as they may be gone by then.
+pub struct ImportedJob {
+    pub clip_name: String,
+    pub room_name: String,
+    pub run_at: i64,
+}
+
+impl ImportedJob {
+    /// Keeps the jobs until the import offered in the message is confirmed or cancelled.
+    pub async fn insert_pending(
+        db: &Pool<Sqlite>,
+        message_id: &MessageId,
+        jobs: Vec<ImportedJob>,
+    ) -> sqlx::Result<()> {
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT INTO pending_imports (message_id, clip_name, room_name, run_at)",
+        );
+        query_builder.push_values(jobs, |mut b, job| {
+            b.push_bind(message_id.0)
+                .push_bind(job.clip_name)
+                .push_bind(job.room_name)
+                .push_bind(job.run_at);
+        });
+        query_builder.build().execute(db).await?;
+        Ok(())
+    }
+
+    /// Removes the jobs of the import offered in the message, returning them.
+    pub async fn take_pending(
+        db: &Pool<Sqlite>,
+        message_id: &MessageId,
+    ) -> sqlx::Result<Vec<ImportedJob>> {
+        let jobs = sqlx::query_as!(
+            ImportedJob,
+            "SELECT clip_name, room_name, run_at FROM pending_imports
+                WHERE message_id = ? ORDER BY run_at",
+            message_id.0
+        )
+        .fetch_all(db)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM pending_imports WHERE message_id = ?",
+            message_id.0
+        )
+        .execute(db)
+        .await?;
+        Ok(jobs)
+    }
+}
+
+/// Shortens a text to at most `max_length` UTF-16 code units, which is how Telegram counts.
+pub fn shorten(text: &str, max_length: usize) -> String {
+    if text.encode_utf16().count() <= max_length {
+        return text.to_owned();
+    }
+    // leaves room for the ellipsis
+    let mut length = 1;
+    text.chars()
+        .take_while(|c| {
+            length += c.len_utf16();
+            length <= max_length
+        })
+        .chain(['…'])
+        .collect()
+}
+
+fn bullet_list(lines: &[String]) -> String {
+    let mut list = lines
+        .iter()
+        .take(MAX_PREVIEW_LINES)
+        .map(|line| shorten(&format!("- {}", line), MAX_LINE_LENGTH))
+        .join("\n");
+    if lines.len() > MAX_PREVIEW_LINES {
+        list += &format!("\n… and {} more", lines.len() - MAX_PREVIEW_LINES);
+    }
+    list
+}
+
+pub fn is_calendar(document: &Document) -> bool {
+    document
+        .file_name
+        .as_deref()
+        .is_some_and(|name| name.to_lowercase().ends_with(".ics"))
+        || document
+            .mime_type
+            .as_ref()
+            .is_some_and(|mime| mime.essence_str() == "text/calendar")
+}
+
+/// Reads an uploaded `.ics` file and offers to schedule its events after showing a preview.
+pub async fn handle_calendar(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    chat_id: ChatId,
+    document: &Document,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !app_config.is_admin(&chat_id.0) {
+        bot.send_message(chat_id, "Insufficient permission.")
+            .await?;
+        return Ok(());
+    }
+    if document.file.size > MAX_CALENDAR_SIZE {
+        bot.send_message(chat_id, "This calendar is too large to import.")
+            .await?;
+        return Ok(());
+    }
+
+    let file = bot.get_file(&document.file.id).await?;
+    let mut content = Vec::new();
+    bot.download_file(&file.path, &mut content).await?;
+
+    let now = Utc::now().with_timezone(&app_config.timezone);
+    let (events, mut skipped) = match parse_calendar(&String::from_utf8_lossy(&content), now) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            bot.send_message(chat_id, format!("Failed to read the calendar: {}", err))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let mut jobs = Vec::new();
+    let mut lines = Vec::new();
+    for event in events {
+        let Some(clip) = Clip::find(db, &event.clip_name).await? else {
+            skipped.push(SkippedEvent {
+                summary: event.summary,
+                reason: SkipReason::UnknownClip(event.clip_name),
+            });
+            continue;
+        };
+        let Some(room) = Room::find(db, &event.room_name).await? else {
+            skipped.push(SkippedEvent {
+                summary: event.summary,
+                reason: SkipReason::UnknownRoom(event.room_name),
+            });
+            continue;
+        };
+
+        let first = format_time(event.occurrences[0].timestamp(), app_config.timezone);
+        lines.push(match event.occurrences.len() {
+            1 => format!(
+                "{}: {} in {} at {}",
+                event.summary, clip.name, room.name, first
+            ),
+            count => format!(
+                "{}: {} in {} {} times from {}",
+                event.summary, clip.name, room.name, count, first
+            ),
+        });
+        jobs.extend(event.occurrences.into_iter().map(|at| ImportedJob {
+            clip_name: clip.name.clone(),
+            room_name: room.name.clone(),
+            run_at: at.timestamp(),
+        }));
+    }
+    jobs.sort_by_key(|job| job.run_at);
+    let truncated = jobs.len() > MAX_IMPORTED_JOBS;
+    jobs.truncate(MAX_IMPORTED_JOBS);
+
+    let mut text = match jobs.len() {
+        0 => "Found nothing to import.".to_owned(),
+        count => format!(
+            "Import {} announcements ({})?\n{}",
+            count,
+            app_config.timezone,
+            bullet_list(&lines)
+        ),
+    };
+    if truncated {
+        text += &format!("\nOnly the first {} are imported.", MAX_IMPORTED_JOBS);
+    }
+    if !skipped.is_empty() {
+        text += &format!(
+            "\n\nSkipped:\n{}",
+            bullet_list(
+                &skipped
+                    .iter()
+                    .map(|event| format!("{}: {}", event.summary, event.reason))
+                    .collect_vec()
+            )
+        );
+    }
+    let text = shorten(&text, MAX_MESSAGE_LENGTH);
+    if jobs.is_empty() {
+        bot.send_message(chat_id, text).await?;
+        return Ok(());
+    }
+
+    let keyboard = InlineDataKeyboard::new().buttons(vec![
+        InlineDataKeyboardButton {
+            text: "Import".into(),
+            data: serde_json::to_string(&CallbackType::ImportSchedules)?,
+        },
+        InlineDataKeyboardButton {
+            text: "Cancel".into(),
+            data: serde_json::to_string(&CallbackType::CancelImport)?,
+        },
+    ]);
+    let keyboard_msg = bot
+        .send_message(chat_id, text)
+        .reply_markup(keyboard.build_inline_keyboard_markup())
+        .await?;
+    keyboard.insert_into_db(db, &keyboard_msg.id).await?;
+    ImportedJob::insert_pending(db, &keyboard_msg.id, jobs).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortens_to_utf16_length() {
+        assert_eq!(shorten("Assembly", 8), "Assembly");
+        assert_eq!(shorten("Assembly", 6), "Assem…");
+        assert_eq!(shorten("🔔🔔🔔", 4), "🔔…");
+    }
+}
@@ -1,41 +1,56 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc, time::Duration};
 
 use sqlx::{Pool, Sqlite};
 use teloxide::{
+    payloads::SendMessageSetters,
     prelude::Requester,
-    types::{MediaKind, Message, MessageKind},
+    types::{FileMeta, KeyboardButton, KeyboardMarkup, MediaKind, Message, MessageKind},
     Bot,
 };
 
-use crate::handle_voice_message::handle_voice_message;
+use crate::{
+    audio::processor::AudioProcessor,
+    clip::Clip,
+    config::AppConfig,
+    dialogues::{DialogueDependency, State},
+    handle_voice_message::{accept_audio, download_audio, handle_voice_message},
+};
 
-pub async fn handle_replies(
+/// The voice message or audio file the message replies to with the duration Telegram reports,
+/// telling the user if there is none.
+pub async fn replied_file<'a>(
     bot: &Bot,
-    db: &Pool<Sqlite>,
-    msg: &Message,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    msg: &'a Message,
+) -> Result<Option<(&'a FileMeta, Option<Duration>)>, Box<dyn Error + Send + Sync>> {
     let reply_msg = match msg.reply_to_message() {
         None => {
             bot.send_message( msg.chat.id,
                             "This command may only be used in reply to an older voice message. Use /help for more information.",
                         )
                         .await?;
-            return Ok(());
+            return Ok(None);
         }
         Some(reply_msg) => reply_msg,
     };
 
-    let file = match &reply_msg.kind {
+    let replied = match &reply_msg.kind {
         MessageKind::Common(common_msg) => match &common_msg.media_kind {
-            MediaKind::Voice(voice) => &voice.voice.file,
-            MediaKind::Audio(audio) => &audio.audio.file,
+            MediaKind::Voice(voice) => (
+                &voice.voice.file,
+                Some(Duration::from_secs(voice.voice.duration.into())),
+            ),
+            MediaKind::Audio(audio) => (
+                &audio.audio.file,
+                Some(Duration::from_secs(audio.audio.duration.into())),
+            ),
+            MediaKind::Document(doc) => (&doc.document.file, None),
             _ => {
                 bot.send_message(
                     msg.chat.id,
                     "The mentioned message has to be a voice message or an audio file.",
                 )
                 .await?;
-                return Ok(());
+                return Ok(None);
             }
         },
         _ => {
@@ -44,11 +59,139 @@ pub async fn handle_replies(
                 "The mentioned message has to be a voice message or an audio file.",
             )
             .await?;
-            return Ok(());
+            return Ok(None);
         }
     };
+    Ok(Some(replied))
+}
+
+pub async fn handle_replies(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    audio_processor: &Arc<AudioProcessor>,
+    msg: &Message,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((file, reported_duration)) = replied_file(bot, msg).await? else {
+        return Ok(());
+    };
+
+    handle_voice_message(
+        &bot,
+        &db,
+        app_config,
+        audio_processor,
+        msg.chat.id,
+        file,
+        reported_duration,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Stores the mentioned audio as a clip.
+pub async fn save_clip(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    msg: &Message,
+    name: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let name = name.trim();
+    if name.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "Please name the clip, i. e. /save Dinner is ready",
+        )
+        .await?;
+        return Ok(());
+    }
+    let Some((file, reported_duration)) = replied_file(bot, msg).await? else {
+        return Ok(());
+    };
+    if Clip::find(db, name).await?.is_some() {
+        bot.send_message(
+            msg.chat.id,
+            format!("A clip named {} already exists.", name),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(mut entry) =
+        download_audio(bot, db, app_config, msg.chat.id, file, reported_duration).await?
+    else {
+        return Ok(());
+    };
+    if accept_audio(bot, db, app_config, msg.chat.id, &mut entry)
+        .await?
+        .is_none()
+    {
+        return Ok(());
+    }
 
-    handle_voice_message(&bot, &db, msg.chat.id, &file.id).await?;
+    let clip = Clip {
+        name: name.to_owned(),
+        unique_id: entry.unique_id,
+    };
+    let text = match clip.insert(db, msg.chat.id.0).await? {
+        true => format!("Saved clip {}. Use /clips to play it.", name),
+        false => format!("A clip named {} already exists.", name),
+    };
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+/// Asks where to play the mentioned audio later on.
+pub async fn schedule_reply(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    dialogue: &DialogueDependency,
+    msg: &Message,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some((file, reported_duration)) = replied_file(bot, msg).await? else {
+        return Ok(());
+    };
+    let room_names: Vec<String> = sqlx::query!("SELECT name FROM rooms ORDER BY sort_index, name")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|room| room.name)
+        .collect();
+    if room_names.is_empty() {
+        bot.send_message(msg.chat.id, "No rooms were defined yet to play this in.")
+            .await?;
+        return Ok(());
+    }
+
+    let Some(mut entry) =
+        download_audio(bot, db, app_config, msg.chat.id, file, reported_duration).await?
+    else {
+        return Ok(());
+    };
+    if accept_audio(bot, db, app_config, msg.chat.id, &mut entry)
+        .await?
+        .is_none()
+    {
+        return Ok(());
+    }
 
+    let buttons = room_names
+        .chunks(3)
+        .map(|row| row.iter().map(KeyboardButton::new).collect::<Vec<_>>());
+    bot.send_message(msg.chat.id, "Where should I play this?")
+        .reply_markup(
+            KeyboardMarkup::new(buttons)
+                .one_time_keyboard()
+                .resize_keyboard(),
+        )
+        .await?;
+    dialogue
+        .update(State::ReceiveScheduleRoom {
+            unique_id: entry.unique_id,
+        })
+        .await?;
     Ok(())
 }
@@ -0,0 +1,91 @@
+use std::{error::Error, sync::Arc};
+
+use sqlx::{Pool, Sqlite};
+use teloxide::{
+    requests::Requester,
+    types::{ChatAction, ChatId},
+    Bot,
+};
+
+use crate::{
+    audio::processor::AudioProcessor,
+    config::AppConfig,
+    handle_voice_message::offer_audio,
+    tts::{synthesize, Speech},
+};
+
+pub async fn handle_text_message(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    audio_processor: &Arc<AudioProcessor>,
+    chat_id: ChatId,
+    text: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if app_config.env.tts_command.is_none() {
+        bot.send_message(chat_id, "Send me a voice message or use /help.")
+            .await?;
+        return Ok(());
+    }
+
+    let speech = Speech::parse(text, &app_config.env.tts_voices);
+    if speech.text.is_empty() {
+        bot.send_message(
+            chat_id,
+            "Send me the text to announce, i. e. /say Dinner is ready.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let max_length = app_config.env.tts_max_length;
+    if speech.text.chars().count() > max_length {
+        bot.send_message(
+            chat_id,
+            format!(
+                "This text is too long, announcements may be at most {} characters long.",
+                max_length
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let room_names: Vec<String> = sqlx::query!("SELECT name FROM rooms")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|room| room.name)
+        .collect();
+    if room_names.is_empty() {
+        bot.send_message(chat_id, "No rooms were defined yet to play this in.")
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_chat_action(chat_id, ChatAction::RecordVoice)
+        .await?;
+    let entry = match synthesize(db, app_config, &speech, None).await {
+        Ok(entry) => entry,
+        Err(err) => {
+            log::error!("failed to synthesize speech: {}", err);
+            bot.send_message(
+                chat_id,
+                "Failed to read out this text :/ Please try this again later.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    entry.record_upload(db, chat_id.0).await?;
+
+    offer_audio(
+        bot,
+        db,
+        app_config,
+        audio_processor,
+        chat_id,
+        room_names,
+        entry,
+    )
+    .await
+}
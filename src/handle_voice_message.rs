@@ -1,19 +1,195 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc, time::Duration};
 
 use itertools::Itertools;
 use sqlx::{Pool, Sqlite};
-use teloxide::{payloads::SendMessageSetters, requests::Requester, types::ChatId, Bot};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatAction, ChatId, FileMeta},
+    Bot,
+};
+use tokio::task;
 
 use crate::{
+    audio::{
+        format_duration,
+        probe::{probe_file, AudioInfo},
+        processor::AudioProcessor,
+    },
+    audio_cache::{fetch_audio, AudioCacheEntry, DownloadAudioError, TELEGRAM_DOWNLOAD_LIMIT},
     callback_handler::CallbackType,
+    config::AppConfig,
     inline_data_keyboard::{InlineDataKeyboard, InlineDataKeyboardButton},
+    player::Repeat,
+    retention::format_file_size,
+    room_group::RoomGroup,
+    room_tree::{level, Entry, LocatedRoom},
 };
 
+const REPEAT_COUNTS: [u32; 3] = [1, 2, 3];
+const REPEAT_INTERVALS: [u64; 3] = [5, 15, 30];
+
+/// Keeps a level of the room tree within a few rows, larger levels are split into pages.
+const LEVEL_PAGE_SIZE: usize = 12;
+
+/// The rooms to play an audio in, followed by rows to pick how often it is repeated.
+/// Rooms are shown level by level of their `location`, starting on the top level.
+/// With a `selection`, room buttons toggle whether to play there instead of playing right away.
+/// Room groups get rows of their own on the top level.
+pub fn offer_keyboard(
+    rooms: &[LocatedRoom],
+    group_names: &[String],
+    file_unique_id: &str,
+    repeat: Repeat,
+    selection: Option<&[String]>,
+    location: &[String],
+) -> serde_json::Result<InlineDataKeyboard> {
+    let button = |text: String, cb_type: CallbackType| -> serde_json::Result<_> {
+        Ok(InlineDataKeyboardButton {
+            text,
+            data: serde_json::to_string(&cb_type)?,
+        })
+    };
+    let checked = |text: String, selected: bool| match selected {
+        true => format!("✓ {}", text),
+        false => text,
+    };
+    let select = |selection: Option<Vec<String>>| CallbackType::SelectRooms {
+        file_unique_id: file_unique_id.to_owned(),
+        repeat,
+        selection,
+        location: location.to_vec(),
+    };
+    let browse = |location: Vec<String>| CallbackType::Browse {
+        file_unique_id: file_unique_id.to_owned(),
+        repeat,
+        selection: selection.map(<[String]>::to_vec),
+        location,
+    };
+
+    let entries: Vec<InlineDataKeyboardButton> = level(rooms, location)
+        .into_iter()
+        .map(|entry| match (entry, selection) {
+            (Entry::Level(level), _) => button(
+                format!("📂 {}", level),
+                browse(location.iter().cloned().chain([level.to_owned()]).collect()),
+            ),
+            (Entry::Room(room_name), None) => button(
+                room_name.to_owned(),
+                CallbackType::PlayAudio {
+                    room_name: room_name.to_owned(),
+                    file_unique_id: file_unique_id.to_owned(),
+                    repeat,
+                },
+            ),
+            (Entry::Room(room_name), Some(selection)) => {
+                let selected = selection.iter().any(|name| name == room_name);
+                button(
+                    checked(room_name.to_owned(), selected),
+                    select(Some(toggle(rooms, selection, room_name))),
+                )
+            }
+        })
+        .try_collect()?;
+
+    let mut rows = Vec::new();
+    if let Some((_, parent)) = location.split_last() {
+        rows.push(vec![button("« Back".into(), browse(parent.to_vec()))?]);
+    }
+    if selection.is_none() && location.is_empty() {
+        for row in group_names.chunks(3) {
+            rows.push(
+                row.iter()
+                    .map(|group_name| {
+                        button(
+                            format!("👥 {}", group_name),
+                            CallbackType::PlayGroup {
+                                group_name: group_name.to_owned(),
+                                file_unique_id: file_unique_id.to_owned(),
+                                repeat,
+                            },
+                        )
+                    })
+                    .try_collect()?,
+            );
+        }
+    }
+    rows.push(match selection {
+        None => vec![button("Several rooms".into(), select(Some(Vec::new())))?],
+        Some(selection) => {
+            let mut row = Vec::new();
+            if !selection.is_empty() {
+                row.push(button(
+                    format!("Play in selected ({})", selection.len()),
+                    CallbackType::PlayAudioIn {
+                        room_names: selection.to_vec(),
+                        file_unique_id: file_unique_id.to_owned(),
+                        repeat,
+                    },
+                )?);
+            }
+            row.push(button("Single room".into(), select(None))?);
+            row
+        }
+    });
+    rows.push(
+        REPEAT_COUNTS
+            .iter()
+            .map(|&count| {
+                button(
+                    checked(format!("{}×", count), count == repeat.count),
+                    CallbackType::SetRepeat {
+                        file_unique_id: file_unique_id.to_owned(),
+                        repeat: Repeat { count, ..repeat },
+                        selection: selection.map(<[String]>::to_vec),
+                        location: location.to_vec(),
+                    },
+                )
+            })
+            .try_collect()?,
+    );
+    if repeat.count > 1 {
+        rows.push(
+            REPEAT_INTERVALS
+                .iter()
+                .map(|&interval| {
+                    button(
+                        checked(format!("every {}s", interval), interval == repeat.interval),
+                        CallbackType::SetRepeat {
+                            file_unique_id: file_unique_id.to_owned(),
+                            repeat: Repeat { interval, ..repeat },
+                            selection: selection.map(<[String]>::to_vec),
+                            location: location.to_vec(),
+                        },
+                    )
+                })
+                .try_collect()?,
+        );
+    }
+    Ok(InlineDataKeyboard::new()
+        .page_size(LEVEL_PAGE_SIZE)
+        .buttons(entries)
+        .footer(rows))
+}
+
+/// Adds the room to the selection or removes it, keeping the selection in the order of the rooms.
+fn toggle(rooms: &[LocatedRoom], selection: &[String], room_name: &str) -> Vec<String> {
+    rooms
+        .iter()
+        .map(|room| &room.name)
+        .filter(|name| (*name == room_name) != selection.contains(name))
+        .cloned()
+        .collect()
+}
+
 pub async fn handle_voice_message(
     bot: &Bot,
     db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    audio_processor: &Arc<AudioProcessor>,
     chat_id: ChatId,
-    voice_file_id: &str,
+    file: &FileMeta,
+    reported_duration: Option<Duration>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let rooms = sqlx::query!("SELECT * FROM rooms").fetch_all(db).await?;
     if rooms.len() <= 0 {
@@ -22,27 +198,250 @@ pub async fn handle_voice_message(
         return Ok(());
     }
 
-    let keyboard = InlineDataKeyboard::new().buttons(
-        rooms
-            .iter()
-            .map(
-                move |room| -> serde_json::Result<InlineDataKeyboardButton> {
-                    Ok(InlineDataKeyboardButton {
-                        text: room.name.to_owned(),
-                        data: serde_json::to_string(&CallbackType::PlayAudio {
-                            room_name: room.name.to_owned(),
-                            voice_file_id: voice_file_id.to_owned(),
-                        })?,
-                    })
-                },
+    let Some(entry) = download_audio(bot, db, app_config, chat_id, file, reported_duration).await?
+    else {
+        return Ok(());
+    };
+    let room_names: Vec<String> = rooms.into_iter().map(|room| room.name).collect();
+    offer_audio(
+        bot,
+        db,
+        app_config,
+        audio_processor,
+        chat_id,
+        room_names,
+        entry,
+    )
+    .await
+}
+
+/// Downloads a file within the limits of the user, telling them if it is too large.
+/// The duration Telegram reports is kept in case the file itself doesn't tell.
+pub async fn download_audio(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    chat_id: ChatId,
+    file: &FileMeta,
+    reported_duration: Option<Duration>,
+) -> Result<Option<AudioCacheEntry>, Box<dyn Error + Send + Sync>> {
+    let limits = app_config.limits(&chat_id.0);
+    let max_file_size = match limits.max_file_size {
+        Some(max_file_size) => max_file_size.min(TELEGRAM_DOWNLOAD_LIMIT as u64),
+        None => TELEGRAM_DOWNLOAD_LIMIT as u64,
+    };
+    if file.size as u64 > max_file_size {
+        bot.send_message(
+            chat_id,
+            format!(
+                "This file is too large, files may be at most {}.",
+                format_file_size(max_file_size)
+            ),
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    // a file sent again is checked thoroughly, which repairs a copy that got corrupted
+    if let Some(entry) = AudioCacheEntry::find(db, &file.unique_id).await? {
+        if !entry.verify(&app_config.audio_dir).await {
+            log::warn!(
+                "cached audio {} failed verification, downloading it again",
+                entry.file_name
+            );
+            entry.remove(db, &app_config.audio_dir).await?;
+        }
+    }
+
+    bot.send_chat_action(chat_id, ChatAction::Typing).await?;
+    match fetch_audio(
+        bot,
+        db,
+        &app_config.audio_dir,
+        &file.id,
+        &file.unique_id,
+        Some(file.size as u64),
+    )
+    .await
+    {
+        Ok(mut entry) => {
+            if let (None, Some(duration)) = (entry.duration(), reported_duration) {
+                entry.set_duration(db, duration).await?;
+            }
+            Ok(Some(entry))
+        }
+        Err(DownloadAudioError::TooBig) => {
+            bot.send_message(
+                chat_id,
+                format!(
+                    "This file is too large, files may be at most {}.",
+                    format_file_size(TELEGRAM_DOWNLOAD_LIMIT as u64)
+                ),
             )
-            .try_collect()?,
-    );
+            .await?;
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Checks that a stored audio can be played and is within the limits of the user, who is
+/// remembered as its sender.
+pub async fn accept_audio(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    chat_id: ChatId,
+    entry: &mut AudioCacheEntry,
+) -> Result<Option<AudioInfo>, Box<dyn Error + Send + Sync>> {
+    let limits = app_config.limits(&chat_id.0);
+    let path = entry.path(&app_config.audio_dir);
+    let mut info = match task::spawn_blocking(move || probe_file(&path)).await? {
+        Ok(info) => info,
+        Err(err) => {
+            log::info!("rejected file {}: {}", entry.file_name, err);
+            entry.remove(db, &app_config.audio_dir).await?;
+            bot.send_message(
+                chat_id,
+                "This doesn't seem to be an audio file I can play. Please send me a voice message or an audio file.",
+            )
+            .await?;
+            return Ok(None);
+        }
+    };
+    entry.record_upload(db, chat_id.0).await?;
+
+    match info.duration {
+        Some(duration) => entry.set_duration(db, duration).await?,
+        // i. e. the duration Telegram reported
+        None => info.duration = entry.duration(),
+    }
+
+    if let Some(max_duration) = limits.max_duration {
+        let max_duration = Duration::from_secs(max_duration);
+        let text = match info.duration {
+            Some(duration) if duration <= max_duration => None,
+            Some(_) => Some(format!(
+                "This audio is too long, announcements may be at most {} long.",
+                format_duration(max_duration)
+            )),
+            None => Some(format!(
+                "I can't tell how long this audio is, announcements may be at most {} long.",
+                format_duration(max_duration)
+            )),
+        };
+        if let Some(text) = text {
+            bot.send_message(chat_id, text).await?;
+            return Ok(None);
+        }
+    }
+    Ok(Some(info))
+}
+
+/// Checks a stored audio and asks where to play it.
+pub async fn offer_audio(
+    bot: &Bot,
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    audio_processor: &Arc<AudioProcessor>,
+    chat_id: ChatId,
+    room_names: Vec<String>,
+    mut entry: AudioCacheEntry,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(info) = accept_audio(bot, db, app_config, chat_id, &mut entry).await? else {
+        return Ok(());
+    };
+
+    let keyboard = offer_keyboard(
+        &LocatedRoom::all(db).await?,
+        &RoomGroup::names(db).await?,
+        &entry.unique_id,
+        Repeat::default(),
+        None,
+        &[],
+    )?;
     let keyboard_msg = bot
-        .send_message(chat_id, "Where should I play this?")
+        .send_message(chat_id, format!("Where should I play this? ({})", info))
         .reply_markup(keyboard.build_inline_keyboard_markup())
         .await?;
     keyboard.insert_into_db(&db, &keyboard_msg.id).await?;
 
+    audio_processor.prefetch(entry.path(&app_config.audio_dir), room_names);
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::InlineKeyboardButton;
+
+    use super::*;
+    use crate::room_tree::parse_location;
+
+    fn room(name: &str, location: &str) -> LocatedRoom {
+        LocatedRoom {
+            name: name.into(),
+            location: parse_location(location),
+        }
+    }
+
+    fn texts(keyboard: &InlineDataKeyboard) -> Vec<Vec<String>> {
+        keyboard
+            .build_inline_keyboard()
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|button: InlineKeyboardButton| button.text)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn levels_have_no_empty_rows() {
+        let rooms = [room("Yard", ""), room("Office", "Main site/Floor 1")];
+        let repeat = Repeat::default();
+
+        let top = offer_keyboard(&rooms, &[], "audio", repeat, None, &[]).unwrap();
+        let top = texts(&top);
+        assert!(top.iter().all(|row| !row.is_empty()));
+        assert_eq!(top[0], ["📂 Main site", "Yard"]);
+        assert_eq!(top[1], ["Several rooms"]);
+
+        let location = parse_location("Main site/Floor 1");
+        let nested = offer_keyboard(&rooms, &[], "audio", repeat, None, &location).unwrap();
+        let nested = texts(&nested);
+        assert!(nested.iter().all(|row| !row.is_empty()));
+        assert_eq!(nested[0], ["Office"]);
+        assert_eq!(nested[1], ["« Back"]);
+    }
+
+    #[test]
+    fn toggling_keeps_the_order_of_rooms() {
+        let rooms = [room("Hall", ""), room("Yard", ""), room("Office", "")];
+
+        let selected: Vec<String> = toggle(&rooms, &[], "Office");
+        assert_eq!(selected, ["Office"]);
+        let selected = toggle(&rooms, &selected, "Hall");
+        assert_eq!(selected, ["Hall", "Office"]);
+        let selected = toggle(&rooms, &selected, "Yard");
+        assert_eq!(selected, ["Hall", "Yard", "Office"]);
+        let selected = toggle(&rooms, &selected, "Hall");
+        assert_eq!(selected, ["Yard", "Office"]);
+        // rooms which are gone drop out of the selection
+        assert!(toggle(&rooms, &["Gone".into(), "Yard".into()], "Yard").is_empty());
+
+        let keyboard = offer_keyboard(
+            &rooms,
+            &[],
+            "audio",
+            Repeat::default(),
+            Some(&selected),
+            &[],
+        )
+        .unwrap();
+        let rows = texts(&keyboard);
+        assert_eq!(rows[0], ["Hall", "✓ Yard", "✓ Office"]);
+        assert_eq!(rows[1], ["Play in selected (2)", "Single room"]);
+    }
+}
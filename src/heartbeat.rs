@@ -3,7 +3,15 @@ use std::time::Duration;
 use reqwest::StatusCode;
 use tokio::time;
 
-use crate::backoff::{Backoff, BackoffVariant};
+use crate::backoff::RetryPolicy;
+
+const HEARTBEAT_RETRY: RetryPolicy = RetryPolicy {
+    initial_delay: Duration::from_secs(1),
+    max_delay: Duration::from_secs(3600),
+    max_attempts: None,
+    max_elapsed: None,
+    jitter: true,
+};
 
 pub struct Heartbeat {
     endpoint: String,
@@ -19,7 +27,7 @@ impl Heartbeat {
         let mut interval = time::interval(self.interval);
         interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
 
-        let mut backoff = Backoff::new(BackoffVariant::Exponential, Some(3600));
+        let mut backoff = HEARTBEAT_RETRY.backoff();
 
         loop {
             let res = reqwest::get(&self.endpoint).await;
@@ -27,7 +35,7 @@ impl Heartbeat {
                 Ok(res) => res,
                 Err(err) => {
                     log::error!("request to health endpoint failed: {}", err);
-                    time::sleep(Duration::from_secs(backoff.next())).await;
+                    time::sleep(backoff.next().unwrap_or(HEARTBEAT_RETRY.max_delay)).await;
                     continue;
                 }
             };
@@ -41,7 +49,7 @@ impl Heartbeat {
                         "request to health endpoint returned bad status code: {}",
                         status
                     );
-                    time::sleep(Duration::from_secs(backoff.next())).await;
+                    time::sleep(backoff.next().unwrap_or(HEARTBEAT_RETRY.max_delay)).await;
                     continue;
                 }
             }
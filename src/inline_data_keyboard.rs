@@ -1,35 +1,101 @@
 use sqlx::{sqlite::SqliteQueryResult, Pool, QueryBuilder, Sqlite};
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId};
 
+/// Starts the callback data of the buttons switching pages, which aren't stored like the others.
+const PAGE_PREFIX: &str = "page:";
+
 pub struct InlineDataKeyboardButton {
     pub text: String,
     pub data: String,
 }
 
+/// Buttons split into pages with rows of `chunk_size` each, followed by rows shown on every page.
 pub struct InlineDataKeyboard {
-    chunk_size: usize,
     buttons: Vec<InlineDataKeyboardButton>,
+    rows: Vec<Vec<InlineDataKeyboardButton>>,
+    chunk_size: usize,
+    page_size: usize,
+    page: usize,
+}
+
+/// The page a button switching pages leads to.
+pub fn parse_page_data(data: &str) -> Option<usize> {
+    data.strip_prefix(PAGE_PREFIX)?.parse().ok()
 }
 
 impl InlineDataKeyboard {
     pub fn new() -> InlineDataKeyboardBuilder {
-        InlineDataKeyboardBuilder { chunk_size: 3 }
+        InlineDataKeyboardBuilder {
+            chunk_size: 3,
+            page_size: 24,
+        }
+    }
+
+    /// Adds rows below the paged buttons, which are shown on every page.
+    pub fn footer(mut self, rows: Vec<Vec<InlineDataKeyboardButton>>) -> Self {
+        self.rows
+            .extend(rows.into_iter().filter(|row| !row.is_empty()));
+        self
+    }
+
+    /// Shows the given page, or the last one if there are fewer.
+    pub fn page(self, page: usize) -> Self {
+        Self {
+            page: page.min(self.pages() - 1),
+            ..self
+        }
+    }
+
+    fn pages(&self) -> usize {
+        self.buttons.len().div_ceil(self.page_size).max(1)
     }
 
     pub fn build_inline_keyboard(&self) -> Vec<Vec<InlineKeyboardButton>> {
-        self.buttons
+        let page_button = |text: String, page: usize| {
+            InlineKeyboardButton::callback(text, format!("{}{}", PAGE_PREFIX, page))
+        };
+
+        let start = self.page * self.page_size;
+        let end = (start + self.page_size).min(self.buttons.len());
+        let mut button_index = start;
+        let mut keyboard: Vec<Vec<InlineKeyboardButton>> = self.buttons[start..end]
             .chunks(self.chunk_size)
-            .enumerate()
-            .map(|(i, row)| {
+            .map(|row| {
                 row.iter()
-                    .enumerate()
-                    .map(|(j, button)| -> InlineKeyboardButton {
-                        let button_index = i * self.chunk_size + j;
-                        InlineKeyboardButton::callback(&button.text, button_index.to_string())
+                    .map(|button| {
+                        button_index += 1;
+                        InlineKeyboardButton::callback(&button.text, (button_index - 1).to_string())
                     })
                     .collect()
             })
-            .collect()
+            .collect();
+
+        let pages = self.pages();
+        if pages > 1 {
+            let mut navigation = Vec::new();
+            if self.page > 0 {
+                navigation.push(page_button("‹ Previous".into(), self.page - 1));
+            }
+            navigation.push(page_button(
+                format!("{} / {}", self.page + 1, pages),
+                self.page,
+            ));
+            if self.page + 1 < pages {
+                navigation.push(page_button("Next ›".into(), self.page + 1));
+            }
+            keyboard.push(navigation);
+        }
+
+        let mut button_index = self.buttons.len();
+        keyboard.extend(self.rows.iter().map(|row| {
+            row.iter()
+                .map(|button| {
+                    button_index += 1;
+                    InlineKeyboardButton::callback(&button.text, (button_index - 1).to_string())
+                })
+                .collect()
+        }));
+        keyboard
     }
 
     pub fn build_inline_keyboard_markup(&self) -> InlineKeyboardMarkup {
@@ -41,23 +107,149 @@ impl InlineDataKeyboard {
         db: &Pool<Sqlite>,
         message_id: &MessageId,
     ) -> sqlx::Result<SqliteQueryResult> {
-        let mut query_builder: QueryBuilder<Sqlite> =
-            QueryBuilder::new("INSERT INTO keyboard_buttons (message_id, button_index, data)");
+        if self.pages() > 1 {
+            let (chunk_size, page_size, page) = (
+                self.chunk_size as i64,
+                self.page_size as i64,
+                self.page as i64,
+            );
+            sqlx::query!(
+                "INSERT INTO keyboard_pages (message_id, chunk_size, page_size, page)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT(message_id) DO UPDATE
+                    SET chunk_size = $2, page_size = $3, page = $4",
+                message_id.0,
+                chunk_size,
+                page_size,
+                page
+            )
+            .execute(db)
+            .await?;
+        }
+
+        let buttons = self.buttons.into_iter().map(|button| (None, button)).chain(
+            self.rows
+                .into_iter()
+                .enumerate()
+                .flat_map(|(row_index, row)| {
+                    row.into_iter()
+                        .map(move |button| (Some(row_index as i64), button))
+                }),
+        );
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT INTO keyboard_buttons (message_id, button_index, data, text, row_index)",
+        );
         query_builder.push_values(
-            self.buttons.into_iter().enumerate(),
-            |mut b, (button_index, button)| {
+            buttons.enumerate(),
+            |mut b, (button_index, (row_index, button))| {
                 b.push_bind(message_id.0)
                     .push_bind(button_index as i64)
-                    .push_bind(button.data);
+                    .push_bind(button.data)
+                    .push_bind(button.text)
+                    .push_bind(row_index);
             },
         );
         query_builder.build().execute(db).await
     }
 
+    /// Restores a keyboard with several pages from the db.
+    async fn find(
+        db: &Pool<Sqlite>,
+        message_id: &MessageId,
+    ) -> sqlx::Result<Option<InlineDataKeyboard>> {
+        let Some(pages) = sqlx::query!(
+            "SELECT chunk_size, page_size, page FROM keyboard_pages WHERE message_id = ?",
+            message_id.0
+        )
+        .fetch_optional(db)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let mut buttons = Vec::new();
+        let mut rows: Vec<Vec<InlineDataKeyboardButton>> = Vec::new();
+        let mut last_row_index = None;
+        for row in sqlx::query!(
+            "SELECT data, text, row_index FROM keyboard_buttons
+                WHERE message_id = ? ORDER BY button_index",
+            message_id.0
+        )
+        .fetch_all(db)
+        .await?
+        {
+            let button = InlineDataKeyboardButton {
+                text: row.text,
+                data: row.data,
+            };
+            match (row.row_index, rows.last_mut()) {
+                (None, _) => buttons.push(button),
+                (Some(row_index), Some(last_row)) if last_row_index == Some(row_index) => {
+                    last_row.push(button)
+                }
+                (Some(row_index), _) => {
+                    rows.push(vec![button]);
+                    last_row_index = Some(row_index);
+                }
+            }
+        }
+        Ok(Some(InlineDataKeyboard {
+            buttons,
+            rows,
+            chunk_size: pages.chunk_size as usize,
+            page_size: pages.page_size as usize,
+            page: pages.page as usize,
+        }))
+    }
+
+    /// Switches the keyboard of a message to another page, returning the markup to show if it
+    /// changed.
+    pub async fn turn_page(
+        db: &Pool<Sqlite>,
+        message_id: &MessageId,
+        page: usize,
+    ) -> sqlx::Result<Option<InlineKeyboardMarkup>> {
+        let Some(keyboard) = Self::find(db, message_id).await? else {
+            return Ok(None);
+        };
+        let shown_page = keyboard.page;
+        let keyboard = keyboard.page(page);
+        if keyboard.page == shown_page {
+            return Ok(None);
+        }
+
+        let page = keyboard.page as i64;
+        sqlx::query!(
+            "UPDATE keyboard_pages SET page = ? WHERE message_id = ?",
+            page,
+            message_id.0
+        )
+        .execute(db)
+        .await?;
+        Ok(Some(keyboard.build_inline_keyboard_markup()))
+    }
+
+    /// The page the keyboard of a message currently shows.
+    pub async fn current_page(db: &Pool<Sqlite>, message_id: &MessageId) -> sqlx::Result<usize> {
+        let page = sqlx::query!(
+            "SELECT page FROM keyboard_pages WHERE message_id = ?",
+            message_id.0
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(page.map_or(0, |row| row.page as usize))
+    }
+
     pub async fn remove_from_db(
         db: &Pool<Sqlite>,
         message_id: &MessageId,
     ) -> sqlx::Result<SqliteQueryResult> {
+        sqlx::query!(
+            "DELETE FROM keyboard_pages WHERE message_id = ?",
+            message_id.0
+        )
+        .execute(db)
+        .await?;
         sqlx::query!(
             "DELETE FROM keyboard_buttons WHERE message_id = ?",
             message_id.0
@@ -69,18 +261,68 @@ impl InlineDataKeyboard {
 
 pub struct InlineDataKeyboardBuilder {
     chunk_size: usize,
+    page_size: usize,
 }
 
 impl InlineDataKeyboardBuilder {
-    #[allow(dead_code)]
     pub fn chunk_size(self, chunk_size: usize) -> Self {
         Self { chunk_size, ..self }
     }
 
+    pub fn page_size(self, page_size: usize) -> Self {
+        Self { page_size, ..self }
+    }
+
     pub fn buttons(self, buttons: Vec<InlineDataKeyboardButton>) -> InlineDataKeyboard {
         InlineDataKeyboard {
-            chunk_size: self.chunk_size,
             buttons,
+            rows: Vec::new(),
+            chunk_size: self.chunk_size,
+            page_size: self.page_size,
+            page: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    use super::*;
+
+    #[test]
+    fn pages_keep_button_indices() {
+        let button = |index: usize| InlineDataKeyboardButton {
+            text: index.to_string(),
+            data: String::new(),
+        };
+        let keyboard = InlineDataKeyboard::new()
+            .page_size(4)
+            .buttons((0..10).map(button).collect())
+            .footer(vec![vec![button(10)], vec![]])
+            .page(1);
+
+        let data: Vec<Vec<String>> = keyboard
+            .build_inline_keyboard()
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|button| match button.kind {
+                        InlineKeyboardButtonKind::CallbackData(data) => data,
+                        _ => String::new(),
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            data,
+            [
+                vec!["4", "5", "6"],
+                vec!["7"],
+                vec!["page:0", "page:1", "page:2"],
+                vec!["10"]
+            ]
+        );
+        assert_eq!(keyboard.page(7).page, 2);
+    }
+}
@@ -1,23 +1,40 @@
 #![forbid(unsafe_code)]
 
 mod ahm;
+mod announcement;
+mod audio;
+mod audio_cache;
 mod auth_handler;
 mod backoff;
+mod calendar;
 mod callback_handler;
+mod clip;
 mod command;
 mod config;
 mod db;
 mod dialogues;
+mod handle_calendar;
 mod handle_replies;
+mod handle_text_message;
 mod handle_voice_message;
 mod heartbeat;
 mod inline_data_keyboard;
 mod msg_handler;
 mod my_chat_member_handler;
 mod player;
+mod privacy;
+mod retention;
+mod room_group;
+mod room_tree;
+mod routine;
+mod schedule;
+mod scheduler;
+mod template;
+mod tts;
 
 use std::{process::exit, sync::Arc, time::Duration};
 
+use audio::processor::{AudioProcessor, AudioProcessorConfig};
 use auth_handler::make_auth_handler;
 use callback_handler::make_callback_handler;
 use config::AppConfig;
@@ -25,6 +42,8 @@ use heartbeat::Heartbeat;
 use msg_handler::make_msg_handler;
 use my_chat_member_handler::make_my_chat_member_handler;
 use player::{Player, PlayerConfig};
+use retention::Retention;
+use scheduler::Scheduler;
 use teloxide::prelude::*;
 
 const ENV_LOGGER_VAR: &str = "TG_VOICE_RELAY_LOG";
@@ -52,21 +71,44 @@ async fn main() {
 
     let bot = Bot::new(&app_config.env.bot_token);
 
-    let player = Player::new(&PlayerConfig::from(&app_config.env));
+    let player = Arc::new(Player::new(&PlayerConfig::from(&app_config.env)));
+
+    let audio_processor = Arc::new(AudioProcessor::new(&AudioProcessorConfig::from(
+        &app_config,
+    )));
 
     let db = db::init(&app_config).await;
 
+    let dialogue_storage = dialogues::open_storage(&app_config).await;
+
+    tokio::spawn(Retention::new(&app_config, db.clone()).task());
+
+    let app_config = Arc::new(app_config);
+
+    tokio::spawn(
+        Scheduler::new(
+            bot.clone(),
+            db.clone(),
+            app_config.clone(),
+            player.clone(),
+            audio_processor.clone(),
+        )
+        .task(),
+    );
+
     Dispatcher::builder(
         bot,
         dptree::entry()
             .branch(make_my_chat_member_handler())
             .branch(make_auth_handler())
-            .branch(make_msg_handler(&app_config).await)
+            .branch(make_msg_handler())
             .branch(make_callback_handler()),
     )
     .dependencies(dptree::deps![
-        Arc::new(app_config),
-        Arc::new(player),
+        app_config,
+        player,
+        audio_processor,
+        dialogue_storage,
         db.clone()
     ])
     .distribution_function(|_| None::<()>)
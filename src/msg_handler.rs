@@ -1,5 +1,5 @@
 use sqlx::{Pool, Sqlite};
-use std::{error::Error, sync::Arc};
+use std::{error::Error, sync::Arc, time::Duration};
 use teloxide::{
     dispatching::DpHandlerDescription,
     prelude::*,
@@ -7,10 +7,13 @@ use teloxide::{
 };
 
 use crate::{
+    audio::processor::AudioProcessor,
     command::Command,
     config::AppConfig,
     dialogues::{self},
+    handle_calendar::{handle_calendar, is_calendar},
     handle_replies::handle_replies,
+    handle_text_message::handle_text_message,
     handle_voice_message::handle_voice_message,
 };
 
@@ -18,18 +21,60 @@ async fn msg_endpoint(
     app_config: Arc<AppConfig>,
     bot: Bot,
     db: Pool<Sqlite>,
+    audio_processor: Arc<AudioProcessor>,
     msg: Message,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     match &msg.kind {
         MessageKind::Common(common_msg) => match &common_msg.media_kind {
             MediaKind::Voice(voice) => {
-                handle_voice_message(&bot, &db, msg.chat.id, &voice.voice.file.id).await?;
+                handle_voice_message(
+                    &bot,
+                    &db,
+                    &app_config,
+                    &audio_processor,
+                    msg.chat.id,
+                    &voice.voice.file,
+                    Some(Duration::from_secs(voice.voice.duration.into())),
+                )
+                .await?;
             }
             MediaKind::Audio(audio) => {
-                handle_voice_message(&bot, &db, msg.chat.id, &audio.audio.file.id).await?;
+                handle_voice_message(
+                    &bot,
+                    &db,
+                    &app_config,
+                    &audio_processor,
+                    msg.chat.id,
+                    &audio.audio.file,
+                    Some(Duration::from_secs(audio.audio.duration.into())),
+                )
+                .await?;
+            }
+            MediaKind::Document(doc) if is_calendar(&doc.document) => {
+                handle_calendar(&bot, &db, &app_config, msg.chat.id, &doc.document).await?;
             }
             MediaKind::Document(doc) => {
-                handle_voice_message(&bot, &db, msg.chat.id, &doc.document.file.id).await?;
+                handle_voice_message(
+                    &bot,
+                    &db,
+                    &app_config,
+                    &audio_processor,
+                    msg.chat.id,
+                    &doc.document.file,
+                    None,
+                )
+                .await?;
+            }
+            MediaKind::Text(text) if !text.text.starts_with('/') => {
+                handle_text_message(
+                    &bot,
+                    &db,
+                    &app_config,
+                    &audio_processor,
+                    msg.chat.id,
+                    &text.text,
+                )
+                .await?;
             }
             _ => {
                 bot.send_message(msg.chat.id, "Send me a voice message or use /help.")
@@ -104,20 +149,25 @@ pub fn make_dot_reply_handler(
 {
     dptree::entry()
         .filter(|msg: Message| matches!(msg.text(), Some(".")))
-        .endpoint(|bot: Bot, db: Pool<Sqlite>, msg: Message| async move {
-            handle_replies(&bot, &db, &msg).await
-        })
+        .endpoint(
+            |app_config: Arc<AppConfig>,
+             bot: Bot,
+             db: Pool<Sqlite>,
+             audio_processor: Arc<AudioProcessor>,
+             msg: Message| async move {
+                handle_replies(&bot, &db, &app_config, &audio_processor, &msg).await
+            },
+        )
 }
 
-pub async fn make_msg_handler(
-    app_config: &AppConfig,
+pub fn make_msg_handler(
 ) -> Handler<'static, DependencyMap, Result<(), Box<dyn Error + Send + Sync>>, DpHandlerDescription>
 {
     Update::filter_message()
         .filter(|msg: Message| msg.chat.id.is_user())
-        .chain(dialogues::set_room::make_inject_handler(app_config).await)
+        .chain(dialogues::make_inject_handler())
         .branch(make_dot_reply_handler())
         .branch(Command::make_handler())
-        .branch(dialogues::set_room::make_endpoint_handler())
+        .branch(dialogues::make_endpoint_handler())
         .branch(dptree::endpoint(msg_endpoint))
 }
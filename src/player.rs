@@ -1,14 +1,23 @@
-use std::{io, time::Duration};
+use std::{io, process::Stdio, time::Duration};
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::{
     process::Command,
     select,
-    sync::{oneshot, Mutex, MutexGuard},
-    time,
+    sync::{oneshot, watch, Mutex, MutexGuard},
+    time::{self, Instant},
 };
 
-use crate::{ahm::AHMConnection, config::EnvConfig};
+use crate::{ahm::AHMConnection, backoff::RetryPolicy, config::EnvConfig};
+
+const MIXER_RETRY: RetryPolicy = RetryPolicy {
+    initial_delay: Duration::from_millis(200),
+    max_delay: Duration::from_secs(2),
+    max_attempts: Some(4),
+    max_elapsed: Some(Duration::from_secs(10)),
+    jitter: false,
+};
 
 #[derive(Error, Debug)]
 pub enum PlayAudioError {
@@ -26,12 +35,108 @@ pub enum StopAudioError {
     AlreadyStopped,
 }
 
+#[derive(Error, Debug)]
+pub enum PauseAudioError {
+    #[error("no audio is being played")]
+    NotPlaying,
+    #[error("the audio is already paused")]
+    AlreadyPaused,
+    #[error("the audio isn't paused")]
+    NotPaused,
+    #[error("pausing is only supported on unix")]
+    Unsupported,
+    #[error("failed to signal the player process: {0}")]
+    Signal(#[from] io::Error),
+}
+
+/// What to do with the process group of the player command.
+#[derive(Clone, Copy)]
+enum GroupSignal {
+    Stop,
+    Continue,
+    Kill,
+}
+
+/// Sends a signal to the process group of the player command.
+#[cfg(unix)]
+fn signal_group(pid: u32, signal: GroupSignal) -> io::Result<()> {
+    use nix::{
+        sys::signal::{killpg, Signal},
+        unistd::Pid,
+    };
+
+    let signal = match signal {
+        GroupSignal::Stop => Signal::SIGSTOP,
+        GroupSignal::Continue => Signal::SIGCONT,
+        GroupSignal::Kill => Signal::SIGKILL,
+    };
+    Ok(killpg(Pid::from_raw(pid as i32), signal)?)
+}
+
+#[cfg(not(unix))]
+fn signal_group(_pid: u32, _signal: GroupSignal) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// How often an audio is played and how many seconds to wait in between.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Repeat {
+    pub count: u32,
+    pub interval: u64,
+}
+
+impl Default for Repeat {
+    fn default() -> Self {
+        Repeat {
+            count: 1,
+            interval: 15,
+        }
+    }
+}
+
+/// What the player is doing right now.
+#[derive(Clone, Copy)]
+pub struct PlaybackState {
+    pub repetition: u32,
+    pub repetitions: u32,
+    /// When the current repetition started, `None` while waiting for it.
+    /// Moved forward on resume, so paused time doesn't count.
+    pub started: Option<Instant>,
+    pub paused_at: Option<Instant>,
+}
+
+impl PlaybackState {
+    pub fn elapsed(&self) -> Duration {
+        match self.started {
+            Some(started) => self
+                .paused_at
+                .unwrap_or_else(Instant::now)
+                .saturating_duration_since(started),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// How a playback ended.
+pub struct PlaybackReport {
+    /// Time spent playing the last repetition, without the delay before it.
+    pub elapsed: Duration,
+    pub repetition: u32,
+    pub repetitions: u32,
+    /// Who stopped the playback, `None` if it finished.
+    pub stopped_by: Option<String>,
+}
+
 pub struct Player {
     player_lock: Mutex<()>,
-    kill_rx: Mutex<Option<oneshot::Receiver<()>>>,
+    kill_tx: Mutex<Option<oneshot::Sender<String>>>,
+    state: watch::Sender<Option<PlaybackState>>,
+    /// The running player process, which leads its own process group.
+    player_pid: std::sync::Mutex<Option<u32>>,
     ahm_endpoint: String,
     player_start_delay: u64,
     player_command: String,
+    player_volume: u32,
 }
 
 pub struct PlayerLock<'a> {
@@ -44,6 +149,15 @@ pub struct PlayerConfig {
     pub ahm_port: u16,
     pub player_start_delay: u64,
     pub player_command: String,
+    pub player_volume: u32,
+}
+
+/// Settings of a room which take precedence over the player config.
+#[derive(Default)]
+pub struct PlayerOverrides {
+    pub start_delay: Option<u64>,
+    pub player_command: Option<String>,
+    pub volume: Option<u32>,
 }
 
 impl From<&EnvConfig> for PlayerConfig {
@@ -51,6 +165,7 @@ impl From<&EnvConfig> for PlayerConfig {
         PlayerConfig {
             player_command: env.player_command.to_owned(),
             player_start_delay: env.player_start_delay,
+            player_volume: env.player_volume,
             ahm_port: env.ahm_port,
             ahm_host: env.ahm_host.to_owned(),
         }
@@ -62,16 +177,23 @@ impl Player {
         let ahm_endpoint = format!("{}:{}", player_config.ahm_host, player_config.ahm_port);
         Player {
             player_lock: Mutex::new(()),
-            kill_rx: Mutex::new(None),
+            kill_tx: Mutex::new(None),
+            state: watch::channel(None).0,
+            player_pid: std::sync::Mutex::new(None),
             ahm_endpoint,
             player_start_delay: player_config.player_start_delay,
             player_command: player_config.player_command.clone(),
+            player_volume: player_config.player_volume,
         }
     }
 
     pub async fn set_channel(&self, channel: u16) -> io::Result<()> {
-        let mut ahm = AHMConnection::connect(&self.ahm_endpoint).await?;
-        ahm.write_preset(channel).await
+        MIXER_RETRY
+            .retry("switching mixer preset", || async {
+                let mut ahm = AHMConnection::connect(&self.ahm_endpoint).await?;
+                ahm.write_preset(channel).await
+            })
+            .await
     }
 
     pub fn try_lock(&self) -> Result<PlayerLock, PlayAudioError> {
@@ -86,64 +208,130 @@ impl Player {
         })
     }
 
-    pub async fn stop_playing(&self) -> Result<(), StopAudioError> {
-        let mut kill_rx = self.kill_rx.lock().await;
-        let _ = kill_rx
+    pub fn subscribe(&self) -> watch::Receiver<Option<PlaybackState>> {
+        self.state.subscribe()
+    }
+
+    fn player_pid(&self) -> std::sync::MutexGuard<'_, Option<u32>> {
+        self.player_pid
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub async fn pause_playing(&self) -> Result<(), PauseAudioError> {
+        if !cfg!(unix) {
+            return Err(PauseAudioError::Unsupported);
+        }
+        let state = *self.state.borrow();
+        match state {
+            None => return Err(PauseAudioError::NotPlaying),
+            Some(state) if state.paused_at.is_some() => return Err(PauseAudioError::AlreadyPaused),
+            Some(_) => {}
+        }
+
+        let pid = *self.player_pid();
+        if let Some(pid) = pid {
+            signal_group(pid, GroupSignal::Stop)?;
+        }
+        self.state.send_modify(|state| {
+            if let Some(state) = state {
+                state.paused_at = Some(Instant::now());
+            }
+        });
+        log::info!("paused playback");
+        Ok(())
+    }
+
+    pub async fn resume_playing(&self) -> Result<(), PauseAudioError> {
+        if !cfg!(unix) {
+            return Err(PauseAudioError::Unsupported);
+        }
+        let state = *self.state.borrow();
+        match state {
+            None => return Err(PauseAudioError::NotPlaying),
+            Some(state) if state.paused_at.is_none() => return Err(PauseAudioError::NotPaused),
+            Some(_) => {}
+        }
+
+        let pid = *self.player_pid();
+        if let Some(pid) = pid {
+            signal_group(pid, GroupSignal::Continue)?;
+        }
+        self.state.send_modify(|state| {
+            if let Some(state) = state {
+                if let (Some(started), Some(paused_at)) = (state.started, state.paused_at) {
+                    state.started = Some(started + paused_at.elapsed());
+                }
+                state.paused_at = None;
+            }
+        });
+        log::info!("resumed playback");
+        Ok(())
+    }
+
+    pub async fn stop_playing(&self, stopped_by: String) -> Result<(), StopAudioError> {
+        let mut kill_tx = self.kill_tx.lock().await;
+        kill_tx
             .take()
             .ok_or(StopAudioError::AlreadyStopped)?
-            .close();
-        Ok(())
+            .send(stopped_by)
+            .map_err(|_| StopAudioError::AlreadyStopped)
     }
 }
 
 impl<'a> PlayerLock<'a> {
-    pub async fn play_audio_file(self, path: &str) -> Result<(), PlayAudioError> {
-        log::info!("starting to play file: {}", path);
-        let (mut kill_tx, kill_rx) = oneshot::channel::<()>();
-
-        {
-            let mut kill_rx_guard = self.player.kill_rx.lock().await;
-            if let Some(mut old_kill_rx) = kill_rx_guard.take() {
-                log::error!("the kill channel has already been initialized for this player, will kill and replace");
-                old_kill_rx.close();
-            }
-            kill_rx_guard.replace(kill_rx);
+    /// Waits for the window to pass, returning who cancelled the playback in the meantime.
+    pub async fn arm(&self, window: Duration) -> Option<String> {
+        let (kill_tx, kill_rx) = oneshot::channel::<String>();
+        self.player.kill_tx.lock().await.replace(kill_tx);
+        let cancelled_by = select! {
+            () = time::sleep(window) => None,
+            cancelled_by = kill_rx => Some(cancelled_by.unwrap_or_else(|_| "unknown".into())),
+        };
+        self.player.kill_tx.lock().await.take();
+        if let Some(cancelled_by) = &cancelled_by {
+            log::info!("playback cancelled by {}", cancelled_by);
         }
-        log::debug!("replaced player kill channel");
+        cancelled_by
+    }
 
+    /// Runs the player command once, logging its output if it fails.
+    async fn run_player_command(
+        &self,
+        player_command: &str,
+        path: &str,
+        volume: u32,
+    ) -> Result<(), PlayAudioError> {
         // todo: store e in CommandParseError
-        let mut args = shell_words::split(&self.player.player_command)
+        let mut args = shell_words::split(player_command)
             .map_err(|_| PlayAudioError::CommandParseError)?
             .into_iter();
         let shell = args.next().ok_or(PlayAudioError::CommandParseError)?;
         let args: Vec<String> = args
-            .map(|arg| if arg == "%f" { path.to_owned() } else { arg })
+            .map(|arg| match arg.as_str() {
+                "%f" => path.to_owned(),
+                _ => arg.replace("%v", &volume.to_string()),
+            })
             .collect();
 
         let all_args_iter = std::iter::once(shell.clone()).chain(args.iter().cloned());
         let cmd_line = shell_words::join(all_args_iter);
 
-        let proc = async {
-            time::sleep(Duration::from_millis(self.player.player_start_delay)).await;
-
-            let proc = Command::new(shell).args(args).kill_on_drop(true).output();
-            proc.await
-        };
-
-        let finished = select! {
-            result = proc => Some(result),
-            () = kill_tx.closed() => None
-        };
-        log::debug!("player done with file: {}", path);
-        if let Ok(mut kill_rx_guard) = self.player.kill_rx.try_lock() {
-            kill_rx_guard.take();
-        }
-        log::debug!("player kill channel cleared");
-
-        let Some(result) = finished else {
-            return Ok(());
-        };
-        let output = result?;
+        let mut command = Command::new(shell);
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // a group of its own, so pausing also reaches processes started by a wrapper script
+        #[cfg(unix)]
+        command.process_group(0);
+        let child = command.spawn()?;
+        *self.player.player_pid() = child.id();
+        let output = child.wait_with_output().await;
+        self.player.player_pid().take();
+        let output = output?;
 
         if let Some(code) = output.status.code() {
             if code != 0 {
@@ -160,9 +348,109 @@ impl<'a> PlayerLock<'a> {
                 );
             }
         }
+        Ok(())
+    }
+
+    pub async fn play_audio_file(
+        self,
+        path: &str,
+        repeat: Repeat,
+        overrides: &PlayerOverrides,
+    ) -> Result<PlaybackReport, PlayAudioError> {
+        log::info!("starting to play file: {}", path);
+        let (kill_tx, kill_rx) = oneshot::channel::<String>();
+
+        {
+            let mut kill_tx_guard = self.player.kill_tx.lock().await;
+            if let Some(old_kill_tx) = kill_tx_guard.take() {
+                log::error!("the kill channel has already been initialized for this player, will kill and replace");
+                let _ = old_kill_tx.send("another playback".into());
+            }
+            kill_tx_guard.replace(kill_tx);
+        }
+        log::debug!("replaced player kill channel");
+
+        let start_delay = overrides
+            .start_delay
+            .unwrap_or(self.player.player_start_delay);
+        let player_command = overrides
+            .player_command
+            .as_deref()
+            .unwrap_or(&self.player.player_command);
+        let volume = overrides.volume.unwrap_or(self.player.player_volume);
+        let repetitions = repeat.count.max(1);
+        let state = &self.player.state;
+        let job = async {
+            let mut state_rx = state.subscribe();
+            for repetition in 1..=repetitions {
+                let delay = match repetition {
+                    1 => Duration::from_millis(start_delay),
+                    _ => Duration::from_secs(repeat.interval),
+                };
+                state.send_modify(|state| {
+                    *state = Some(PlaybackState {
+                        repetition,
+                        repetitions,
+                        started: None,
+                        paused_at: state.and_then(|state| state.paused_at),
+                    })
+                });
+                time::sleep(delay).await;
+                // a pause between repetitions holds the next one
+                let _ = state_rx
+                    .wait_for(|state| !state.is_some_and(|state| state.paused_at.is_some()))
+                    .await;
+
+                state.send_replace(Some(PlaybackState {
+                    repetition,
+                    repetitions,
+                    started: Some(Instant::now()),
+                    paused_at: None,
+                }));
+                self.run_player_command(player_command, path, volume)
+                    .await?;
+            }
+            Result::<(), PlayAudioError>::Ok(())
+        };
+
+        let finished = select! {
+            result = job => Ok(result),
+            stopped_by = kill_rx => Err(stopped_by.unwrap_or_else(|_| "unknown".into())),
+        };
+        log::debug!("player done with file: {}", path);
+        if let Ok(mut kill_tx_guard) = self.player.kill_tx.try_lock() {
+            kill_tx_guard.take();
+        }
+        log::debug!("player kill channel cleared");
+
+        // kill_on_drop only reaches the player process itself, not the rest of its group
+        let pid = self.player.player_pid().take();
+        if let Some(pid) = pid {
+            if let Err(err) = signal_group(pid, GroupSignal::Kill) {
+                log::warn!("failed to kill player process group: {}", err);
+            }
+        }
+
+        let last_state = state.send_replace(None);
+        let report = PlaybackReport {
+            elapsed: last_state.map_or(Duration::ZERO, |state| state.elapsed()),
+            repetition: last_state.map_or(0, |state| state.repetition),
+            repetitions,
+            stopped_by: None,
+        };
+        match finished {
+            Ok(result) => result?,
+            Err(stopped_by) => {
+                log::info!("playback stopped by {}", stopped_by);
+                return Ok(PlaybackReport {
+                    stopped_by: Some(stopped_by),
+                    ..report
+                });
+            }
+        }
 
         drop(self.guard);
-        Ok(())
+        Ok(report)
     }
 }
 
@@ -180,6 +468,7 @@ mod tests {
             ahm_host: "127.0.0.1".into(),
             player_start_delay: 250,
             player_command: "sh -c %f".into(),
+            player_volume: 100,
         };
         Arc::new(Player::new(&config))
     }
@@ -206,7 +495,7 @@ mod tests {
                 let lock1 = player.try_lock().expect("lock 1 failed");
                 let start = Instant::now();
                 lock1
-                    .play_audio_file(&shell)
+                    .play_audio_file(&shell, Repeat::default(), &PlayerOverrides::default())
                     .await
                     .expect("lock 1 command failed");
                 assert!(
@@ -231,7 +520,7 @@ mod tests {
 
                 let lock3 = player.try_lock().expect("still locked after play");
                 lock3
-                    .play_audio_file(&shell)
+                    .play_audio_file(&shell, Repeat::default(), &PlayerOverrides::default())
                     .await
                     .expect("lock 3 command failed");
             }
@@ -249,7 +538,7 @@ mod tests {
             async move {
                 let lock1 = player.try_lock().expect("lock 1 failed");
                 lock1
-                    .play_audio_file(&shell)
+                    .play_audio_file(&shell, Repeat::default(), &PlayerOverrides::default())
                     .await
                     .expect("lock 1 command failed");
             }
@@ -264,14 +553,17 @@ mod tests {
                 let stop1 = Box::pin({
                     let player = player.clone();
                     async move {
-                        player.stop_playing().await.expect("stop 1 failed");
+                        player
+                            .stop_playing("test".into())
+                            .await
+                            .expect("stop 1 failed");
                     }
                 });
                 let stop2 = Box::pin({
                     let player = player.clone();
                     async move {
                         player
-                            .stop_playing()
+                            .stop_playing("test".into())
                             .await
                             .expect_err("stop 2 should have failed");
                     }
@@ -282,7 +574,7 @@ mod tests {
 
                 let lock2 = player.try_lock().expect("lock 2 failed");
                 lock2
-                    .play_audio_file(&shell)
+                    .play_audio_file(&shell, Repeat::default(), &PlayerOverrides::default())
                     .await
                     .expect("lock 2 command failed");
             }
@@ -293,6 +585,124 @@ mod tests {
         player.try_lock().expect("lock 3 failed");
     }
 
+    #[tokio::test]
+    async fn player_repeat() {
+        let player = make_player();
+        let repeat = Repeat {
+            count: 3,
+            interval: 1,
+        };
+
+        let start = Instant::now();
+        let report = player
+            .try_lock()
+            .expect("lock failed")
+            .play_audio_file("sleep 1", repeat, &PlayerOverrides::default())
+            .await
+            .expect("command failed");
+        assert!(start.elapsed().as_millis() >= 5250, "repeated too fast");
+        assert_eq!((report.repetition, report.repetitions), (3, 3));
+        assert!(report.stopped_by.is_none());
+    }
+
+    #[tokio::test]
+    async fn player_repeat_stop() {
+        let player = make_player();
+        let repeat = Repeat {
+            count: 3,
+            interval: 1,
+        };
+
+        let stop = tokio::spawn({
+            let player = player.clone();
+            async move {
+                // waiting between the first and the second repetition
+                tokio::time::sleep(Duration::from_millis(1750)).await;
+                player
+                    .stop_playing("test".into())
+                    .await
+                    .expect("stop failed");
+            }
+        });
+        let report = player
+            .try_lock()
+            .expect("lock failed")
+            .play_audio_file("sleep 1", repeat, &PlayerOverrides::default())
+            .await
+            .expect("command failed");
+        stop.await.expect("stop task failed");
+
+        assert_eq!(report.repetition, 2);
+        assert_eq!(report.stopped_by.as_deref(), Some("test"));
+        assert!(player.subscribe().borrow().is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn player_pause() {
+        fn is_stopped(pid: u32) -> bool {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).expect("no stat");
+            stat.contains(") T ")
+        }
+
+        let player = make_player();
+        assert!(matches!(
+            player.pause_playing().await,
+            Err(PauseAudioError::NotPlaying)
+        ));
+
+        let control = tokio::spawn({
+            let player = player.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(750)).await;
+                player.pause_playing().await.expect("pause failed");
+                let pid = player.player_pid().expect("no player process");
+                assert!(is_stopped(pid), "player process still running");
+                assert!(matches!(
+                    player.pause_playing().await,
+                    Err(PauseAudioError::AlreadyPaused)
+                ));
+
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                player.resume_playing().await.expect("resume failed");
+                assert!(!is_stopped(pid), "player process still stopped");
+            }
+        });
+        let report = player
+            .try_lock()
+            .expect("lock failed")
+            .play_audio_file("sleep 2", Repeat::default(), &PlayerOverrides::default())
+            .await
+            .expect("command failed");
+        control.await.expect("control task failed");
+
+        let elapsed = report.elapsed.as_secs_f64();
+        assert!(elapsed < 2.5, "paused time counted, elapsed {}s", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn player_arm_cancel() {
+        let player = make_player();
+        let lock = player.try_lock().expect("lock failed");
+        assert_eq!(lock.arm(Duration::from_secs(5)).await, None);
+
+        let cancel = tokio::spawn({
+            let player = player.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                player
+                    .stop_playing("test".into())
+                    .await
+                    .expect("cancel failed");
+            }
+        });
+        assert_eq!(
+            lock.arm(Duration::from_secs(5)).await.as_deref(),
+            Some("test")
+        );
+        cancel.await.expect("cancel task failed");
+    }
+
     #[tokio::test]
     async fn player_kill_no_output() {
         player_kill("sleep 3").await;
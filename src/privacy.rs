@@ -0,0 +1,198 @@
+use std::{fmt, path::Path};
+
+use sqlx::{Pool, Sqlite};
+
+use crate::{audio_cache::AudioCacheEntry, retention::format_file_size};
+
+#[derive(Default)]
+pub struct ForgetReport {
+    pub uploads: usize,
+    pub audios: usize,
+    pub bytes: u64,
+}
+
+impl fmt::Display for ForgetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Deleted {} sent audios, {} stored files ({}).",
+            self.uploads,
+            self.audios,
+            format_file_size(self.bytes)
+        )
+    }
+}
+
+/// Drops the keyboards offering an audio, they would reveal who sent it.
+async fn remove_keyboards(db: &Pool<Sqlite>, unique_id: &str) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+    let message_ids = sqlx::query!(
+        "SELECT DISTINCT message_id FROM keyboard_buttons
+            WHERE json_extract(data, '$.PlayAudio.file_unique_id') = $1
+                OR json_extract(data, '$.PlayAudioIn.file_unique_id') = $1
+                OR json_extract(data, '$.PlayGroup.file_unique_id') = $1
+                OR json_extract(data, '$.SelectRooms.file_unique_id') = $1",
+        unique_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for row in message_ids {
+        sqlx::query!(
+            "DELETE FROM keyboard_pages WHERE message_id = ?",
+            row.message_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM keyboard_buttons WHERE message_id = ?",
+            row.message_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// Deletes an audio with everything referring to it.
+async fn erase_audio(
+    db: &Pool<Sqlite>,
+    audio_dir: &Path,
+    entry: AudioCacheEntry,
+) -> sqlx::Result<u64> {
+    remove_keyboards(db, &entry.unique_id).await?;
+    entry.remove(db, audio_dir).await
+}
+
+/// Deletes the upload history and scheduled announcements of a user and every audio or
+/// announcement text nobody else has sent.
+pub async fn forget_user(
+    db: &Pool<Sqlite>,
+    audio_dir: &Path,
+    user_id: i64,
+) -> sqlx::Result<ForgetReport> {
+    let mut report = ForgetReport::default();
+
+    let uploads = sqlx::query!(
+        "SELECT unique_id FROM audio_uploads WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+    sqlx::query!("DELETE FROM audio_uploads WHERE user_id = ?", user_id)
+        .execute(db)
+        .await?;
+    sqlx::query!("DELETE FROM schedules WHERE created_by = ?", user_id)
+        .execute(db)
+        .await?;
+    report.uploads = uploads.len();
+
+    for upload in uploads {
+        // saved clips belong to everyone and are only deleted with the clip
+        let shared = sqlx::query!(
+            "SELECT (SELECT COUNT(*) FROM audio_uploads WHERE unique_id = $1)
+                + (SELECT COUNT(*) FROM clips WHERE unique_id = $1)
+                + (SELECT COUNT(*) FROM schedules WHERE unique_id = $1) AS \"count!\"",
+            upload.unique_id
+        )
+        .fetch_one(db)
+        .await?
+        .count
+            > 0;
+        if shared {
+            continue;
+        }
+        // the text of an announcement is kept even if its file is gone already
+        sqlx::query!("DELETE FROM speech WHERE unique_id = ?", upload.unique_id)
+            .execute(db)
+            .await?;
+        if let Some(entry) = AudioCacheEntry::find(db, &upload.unique_id).await? {
+            report.bytes += erase_audio(db, audio_dir, entry).await?;
+            report.audios += 1;
+        }
+    }
+
+    log::info!(
+        "forgot user {}: {} uploads, {} audios",
+        user_id,
+        report.uploads,
+        report.audios
+    );
+    Ok(report)
+}
+
+/// Deletes uploads older than the unix timestamp `cutoff` and every audio which wasn't sent
+/// again since, except for saved clips and scheduled audios. Returns the number of deleted audios and freed bytes.
+pub async fn expire_audio(
+    db: &Pool<Sqlite>,
+    audio_dir: &Path,
+    cutoff: i64,
+) -> sqlx::Result<(usize, u64)> {
+    sqlx::query!("DELETE FROM audio_uploads WHERE created_at < ?", cutoff)
+        .execute(db)
+        .await?;
+    let expired = sqlx::query_as!(
+        AudioCacheEntry,
+        "SELECT unique_id, file_id, file_name, size, duration_ms, checksum
+            FROM audio_cache WHERE created_at < ? AND NOT EXISTS (
+                SELECT 1 FROM audio_uploads WHERE audio_uploads.unique_id = audio_cache.unique_id
+            ) AND NOT EXISTS (
+                SELECT 1 FROM clips WHERE clips.unique_id = audio_cache.unique_id
+            ) AND NOT EXISTS (
+                SELECT 1 FROM schedules WHERE schedules.unique_id = audio_cache.unique_id
+            )",
+        cutoff
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut freed = 0;
+    let count = expired.len();
+    for entry in expired {
+        freed += erase_audio(db, audio_dir, entry).await?;
+    }
+    Ok((count, freed))
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::{db, handle_voice_message::offer_keyboard, player::Repeat, room_tree::LocatedRoom};
+
+    async fn count(db: &Pool<Sqlite>, table: &str, message_id: i32) -> i64 {
+        sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE message_id = ?",
+            table
+        ))
+        .bind(message_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn removes_paged_offer_keyboards() {
+        let db = db::memory().await;
+        // more rooms than fit on a page
+        let rooms: Vec<LocatedRoom> = (0..20)
+            .map(|index| LocatedRoom {
+                name: format!("Room {}", index),
+                location: Vec::new(),
+            })
+            .collect();
+        for (message_id, unique_id) in [(1, "audio"), (2, "other")] {
+            offer_keyboard(&rooms, &[], unique_id, Repeat::default(), None, &[])
+                .unwrap()
+                .insert_into_db(&db, &MessageId(message_id))
+                .await
+                .unwrap();
+        }
+
+        remove_keyboards(&db, "audio").await.unwrap();
+        assert_eq!(count(&db, "keyboard_buttons", 1).await, 0);
+        assert_eq!(count(&db, "keyboard_pages", 1).await, 0);
+        assert!(count(&db, "keyboard_buttons", 2).await > 0);
+        assert_eq!(count(&db, "keyboard_pages", 2).await, 1);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashSet,
+    fmt, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use sqlx::{Pool, Sqlite};
+use thiserror::Error;
+use tokio::{fs, time};
+
+use crate::{audio_cache::AudioCacheEntry, config::AppConfig, privacy::expire_audio};
+
+/// Files without a cache entry are only removed after this time, so running downloads are kept.
+const ORPHAN_MIN_AGE: Duration = Duration::from_secs(3600);
+
+#[derive(Clone)]
+pub struct RetentionPolicy {
+    pub retention_period: Option<Duration>,
+    pub max_age: Option<Duration>,
+    pub max_total_size: Option<u64>,
+}
+
+impl From<&AppConfig> for RetentionPolicy {
+    fn from(app_config: &AppConfig) -> Self {
+        RetentionPolicy {
+            retention_period: app_config
+                .env
+                .audio_retention_days
+                .map(|days| Duration::from_secs(days * 24 * 3600)),
+            max_age: app_config
+                .env
+                .audio_max_age_days
+                .map(|days| Duration::from_secs(days * 24 * 3600)),
+            max_total_size: app_config.env.audio_max_total_size,
+        }
+    }
+}
+
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retention_period {
+            Some(period) => write!(
+                f,
+                "deleted {} days after sending",
+                period.as_secs() / (24 * 3600)
+            )?,
+            None => write!(f, "kept after sending")?,
+        }
+        match self.max_age {
+            Some(max_age) => write!(f, ", max age {} days", max_age.as_secs() / (24 * 3600))?,
+            None => write!(f, ", no max age")?,
+        }
+        match self.max_total_size {
+            Some(max_total_size) => write!(f, ", max size {}", format_file_size(max_total_size)),
+            None => write!(f, ", no max size"),
+        }
+    }
+}
+
+pub fn format_file_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024 * 1024) as f64)
+}
+
+#[derive(Default)]
+pub struct PurgeReport {
+    pub entries: usize,
+    pub orphans: usize,
+    pub bytes: u64,
+}
+
+impl fmt::Display for PurgeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "removed {} audios and {} orphaned files, freeing {}",
+            self.entries,
+            self.orphans,
+            format_file_size(self.bytes)
+        )
+    }
+}
+
+pub struct StorageUsage {
+    pub entries: usize,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum RetentionError {
+    #[error("failed to read audio dir: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to update audio cache: {0}")]
+    Db(#[from] sqlx::Error),
+}
+
+struct StoredFile {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+async fn stored_files(audio_dir: &Path) -> io::Result<Vec<StoredFile>> {
+    let mut files = Vec::new();
+    let mut dir = fs::read_dir(audio_dir).await?;
+    while let Some(dir_entry) = dir.next_entry().await? {
+        let metadata = dir_entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+        files.push(StoredFile {
+            path: dir_entry.path(),
+            name: dir_entry.file_name().to_string_lossy().into_owned(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    Ok(files)
+}
+
+pub async fn usage(db: &Pool<Sqlite>, audio_dir: &Path) -> Result<StorageUsage, RetentionError> {
+    let entries = sqlx::query!("SELECT COUNT(*) AS count FROM audio_cache")
+        .fetch_one(db)
+        .await?
+        .count as usize;
+    let files = stored_files(audio_dir).await?;
+    Ok(StorageUsage {
+        entries,
+        files: files.len(),
+        bytes: files.iter().map(|file| file.size).sum(),
+    })
+}
+
+/// Deletes files nobody knows about, audio older than the max age and the least recently
+/// used audio beyond the max total size. With `all` every evictable audio is deleted.
+pub async fn purge(
+    db: &Pool<Sqlite>,
+    audio_dir: &Path,
+    policy: &RetentionPolicy,
+    all: bool,
+) -> Result<PurgeReport, RetentionError> {
+    let mut report = PurgeReport::default();
+    let now = SystemTime::now();
+
+    let known: HashSet<String> = sqlx::query!("SELECT unique_id FROM audio_cache")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| row.unique_id)
+        .collect();
+    let mut total_size = 0;
+    for file in stored_files(audio_dir).await? {
+        let unique_id = file.name.split('.').next().unwrap_or_default();
+        let age = file
+            .modified
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if known.contains(unique_id) || age < ORPHAN_MIN_AGE {
+            total_size += file.size;
+            continue;
+        }
+        match fs::remove_file(&file.path).await {
+            Ok(()) => {
+                report.orphans += 1;
+                report.bytes += file.size;
+            }
+            Err(err) => log::error!("failed to remove orphaned file {}: {}", file.name, err),
+        }
+    }
+
+    let unix_now = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    if let Some(period) = policy.retention_period {
+        let (expired, freed) =
+            expire_audio(db, audio_dir, unix_now - period.as_secs() as i64).await?;
+        total_size = total_size.saturating_sub(freed);
+        report.entries += expired;
+        report.bytes += freed;
+    }
+    for (entry, last_used_at) in AudioCacheEntry::evictable(db).await? {
+        let expired = policy
+            .max_age
+            .is_some_and(|max_age| unix_now - last_used_at > max_age.as_secs() as i64);
+        let too_big = policy
+            .max_total_size
+            .is_some_and(|max_total_size| total_size > max_total_size);
+        if !(all || expired || too_big) {
+            // entries are sorted by last use, so all following ones are kept as well
+            break;
+        }
+        let freed = entry.remove(db, audio_dir).await?;
+        total_size = total_size.saturating_sub(freed);
+        report.entries += 1;
+        report.bytes += freed;
+    }
+
+    Ok(report)
+}
+
+pub struct Retention {
+    db: Pool<Sqlite>,
+    audio_dir: PathBuf,
+    policy: RetentionPolicy,
+    interval: Duration,
+}
+
+impl Retention {
+    pub fn new(app_config: &AppConfig, db: Pool<Sqlite>) -> Retention {
+        Retention {
+            db,
+            audio_dir: app_config.audio_dir.clone(),
+            policy: RetentionPolicy::from(app_config),
+            interval: Duration::from_millis(app_config.env.audio_cleanup_interval),
+        }
+    }
+
+    pub async fn task(self) {
+        let mut interval = time::interval(self.interval);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            match purge(&self.db, &self.audio_dir, &self.policy, false).await {
+                Ok(report) if report.entries + report.orphans > 0 => {
+                    log::info!("audio cleanup {}", report)
+                }
+                Ok(_) => {}
+                Err(err) => log::error!("audio cleanup failed: {}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use sqlx::{Pool, Sqlite};
+
+/// Rooms announced in together, i. e. "All floors". A group with its own preset plays
+/// there once instead of in each room.
+pub struct RoomGroup {
+    pub name: String,
+    pub preset: Option<i64>,
+    pub rooms: Vec<String>,
+}
+
+impl RoomGroup {
+    pub async fn all(db: &Pool<Sqlite>) -> sqlx::Result<Vec<RoomGroup>> {
+        let groups = sqlx::query!("SELECT name, preset FROM room_groups ORDER BY name")
+            .fetch_all(db)
+            .await?;
+        let mut members: HashMap<String, Vec<String>> = HashMap::new();
+        for member in
+            sqlx::query!("SELECT group_name, room_name FROM room_group_members ORDER BY room_name")
+                .fetch_all(db)
+                .await?
+        {
+            members
+                .entry(member.group_name)
+                .or_default()
+                .push(member.room_name);
+        }
+        Ok(groups
+            .into_iter()
+            .map(|group| RoomGroup {
+                rooms: members.remove(&group.name).unwrap_or_default(),
+                name: group.name,
+                preset: group.preset,
+            })
+            .collect())
+    }
+
+    pub async fn find(db: &Pool<Sqlite>, name: &str) -> sqlx::Result<Option<RoomGroup>> {
+        Ok(Self::all(db)
+            .await?
+            .into_iter()
+            .find(|group| group.name == name))
+    }
+
+    pub async fn names(db: &Pool<Sqlite>) -> sqlx::Result<Vec<String>> {
+        let rows = sqlx::query!("SELECT name FROM room_groups ORDER BY name")
+            .fetch_all(db)
+            .await?;
+        Ok(rows.into_iter().map(|row| row.name).collect())
+    }
+
+    /// Creates the group or replaces its preset and rooms.
+    pub async fn save(&self, db: &Pool<Sqlite>) -> sqlx::Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "INSERT INTO room_groups (name, preset) VALUES ($1, $2)
+                ON CONFLICT(name) DO UPDATE SET preset = $2",
+            self.name,
+            self.preset
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM room_group_members WHERE group_name = ?",
+            self.name
+        )
+        .execute(&mut *tx)
+        .await?;
+        for room_name in &self.rooms {
+            sqlx::query!(
+                "INSERT INTO room_group_members (group_name, room_name) VALUES (?, ?)",
+                self.name,
+                room_name
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    pub async fn delete(db: &Pool<Sqlite>, name: &str) -> sqlx::Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!("DELETE FROM room_group_members WHERE group_name = ?", name)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM room_groups WHERE name = ?", name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    /// Drops a deleted room from all groups.
+    pub async fn remove_room(db: &Pool<Sqlite>, room_name: &str) -> sqlx::Result<()> {
+        sqlx::query!(
+            "DELETE FROM room_group_members WHERE room_name = ?",
+            room_name
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    #[tokio::test]
+    async fn save_and_remove_rooms() {
+        let db = db::memory().await;
+
+        let mut group = RoomGroup {
+            name: "All floors".into(),
+            preset: None,
+            rooms: vec!["Hall".into(), "Yard".into()],
+        };
+        group.save(&db).await.unwrap();
+        group.preset = Some(12);
+        group.rooms = vec!["Gym".into(), "Hall".into()];
+        group.save(&db).await.unwrap();
+
+        RoomGroup::remove_room(&db, "Hall").await.unwrap();
+        let saved = RoomGroup::find(&db, "All floors").await.unwrap().unwrap();
+        assert_eq!(saved.preset, Some(12));
+        assert_eq!(saved.rooms, ["Gym"]);
+
+        RoomGroup::delete(&db, "All floors").await.unwrap();
+        assert!(RoomGroup::all(&db).await.unwrap().is_empty());
+    }
+}
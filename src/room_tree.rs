@@ -0,0 +1,134 @@
+use sqlx::{Pool, Sqlite};
+
+/// Separates the levels of a location, i. e. "Main site/Building A/Floor 2".
+const SEPARATOR: char = '/';
+
+/// A room with the levels it is nested in, from the outermost to the innermost one.
+/// Rooms without a location are listed on the top level.
+pub struct LocatedRoom {
+    pub name: String,
+    pub location: Vec<String>,
+}
+
+/// What a level of the tree shows, nested levels come before the rooms.
+#[derive(Debug, PartialEq)]
+pub enum Entry<'a> {
+    Level(&'a str),
+    Room(&'a str),
+}
+
+impl LocatedRoom {
+    pub async fn all(db: &Pool<Sqlite>) -> sqlx::Result<Vec<LocatedRoom>> {
+        let rows = sqlx::query!("SELECT name, location FROM rooms ORDER BY sort_index, name")
+            .fetch_all(db)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| LocatedRoom {
+                name: row.name,
+                location: parse_location(row.location.as_deref().unwrap_or_default()),
+            })
+            .collect())
+    }
+}
+
+/// Moves a room up or down in the order rooms are listed in, renumbering all of them.
+/// Returns whether the room moved.
+pub async fn move_room(db: &Pool<Sqlite>, name: &str, offset: i64) -> sqlx::Result<bool> {
+    let mut names: Vec<String> = sqlx::query!("SELECT name FROM rooms ORDER BY sort_index, name")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| row.name)
+        .collect();
+    let Some(index) = names.iter().position(|room_name| room_name == name) else {
+        return Ok(false);
+    };
+    let target = (index as i64 + offset).clamp(0, names.len() as i64 - 1) as usize;
+    if target == index {
+        return Ok(false);
+    }
+    let room_name = names.remove(index);
+    names.insert(target, room_name);
+
+    let mut tx = db.begin().await?;
+    for (sort_index, name) in names.iter().enumerate() {
+        let sort_index = sort_index as i64;
+        sqlx::query!(
+            "UPDATE rooms SET sort_index = ? WHERE name = ?",
+            sort_index,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub fn parse_location(text: &str) -> Vec<String> {
+    text.split(SEPARATOR)
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+pub fn format_location(location: &[String]) -> String {
+    location.join(&SEPARATOR.to_string())
+}
+
+/// The levels and rooms directly below `location`.
+pub fn level<'a>(rooms: &'a [LocatedRoom], location: &[String]) -> Vec<Entry<'a>> {
+    let mut levels = Vec::new();
+    let mut room_names = Vec::new();
+    for room in rooms
+        .iter()
+        .filter(|room| room.location.starts_with(location))
+    {
+        match room.location.get(location.len()) {
+            Some(level) if !levels.contains(&level.as_str()) => levels.push(level.as_str()),
+            Some(_) => {}
+            None => room_names.push(room.name.as_str()),
+        }
+    }
+    levels
+        .into_iter()
+        .map(Entry::Level)
+        .chain(room_names.into_iter().map(Entry::Room))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_levels_before_rooms() {
+        let room = |name: &str, location: &str| LocatedRoom {
+            name: name.into(),
+            location: parse_location(location),
+        };
+        let rooms = [
+            room("Yard", ""),
+            room("Kitchen", "Main site / Building A/Floor 1"),
+            room("Office", "Main site/Building A/Floor 2"),
+            room("Lobby", "Main site/Building A/"),
+            room("Gym", "Main site/Building B/Floor 1"),
+        ];
+
+        assert_eq!(
+            level(&rooms, &[]),
+            [Entry::Level("Main site"), Entry::Room("Yard")]
+        );
+        assert_eq!(
+            level(&rooms, &parse_location("Main site/Building A")),
+            [
+                Entry::Level("Floor 1"),
+                Entry::Level("Floor 2"),
+                Entry::Room("Lobby")
+            ]
+        );
+        assert!(level(&rooms, &parse_location("Elsewhere")).is_empty());
+    }
+}
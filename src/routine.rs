@@ -0,0 +1,376 @@
+use std::{collections::HashSet, str::FromStr};
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use sqlx::{Pool, Sqlite};
+use thiserror::Error;
+
+/// Rules which don't match within this many days, i. e. because of holidays, are considered done.
+const MAX_LOOKAHEAD_DAYS: u32 = 400;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ParseRuleError {
+    #[error("expected days followed by times, i. e. Mon-Fri 08:00,12:30")]
+    Format,
+    #[error("unknown day: {0}")]
+    Day(String),
+    #[error("invalid time: {0}")]
+    Time(String),
+}
+
+/// When a routine plays: days like `Mon-Fri`, `Sat,Sun`, `daily`, `weekdays` or `weekends`,
+/// followed by times like `08:00,12:30`.
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    weekdays: [bool; 7],
+    times: Vec<NaiveTime>,
+}
+
+fn parse_weekday(text: &str) -> Result<Weekday, ParseRuleError> {
+    text.parse()
+        .map_err(|_| ParseRuleError::Day(text.to_owned()))
+}
+
+impl FromStr for Rule {
+    type Err = ParseRuleError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (days, times) = text
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or(ParseRuleError::Format)?;
+
+        let mut weekdays = [false; 7];
+        match days.to_lowercase().as_str() {
+            "daily" => weekdays = [true; 7],
+            "weekdays" => weekdays[..5].fill(true),
+            "weekends" => weekdays[5..].fill(true),
+            _ => {
+                for part in days.split(',') {
+                    let (first, last) = match part.split_once('-') {
+                        Some((first, last)) => (parse_weekday(first)?, parse_weekday(last)?),
+                        None => (parse_weekday(part)?, parse_weekday(part)?),
+                    };
+                    // ranges may wrap around the weekend, i. e. Fri-Mon
+                    let mut day = first;
+                    weekdays[day.num_days_from_monday() as usize] = true;
+                    while day != last {
+                        day = day.succ();
+                        weekdays[day.num_days_from_monday() as usize] = true;
+                    }
+                }
+            }
+        }
+
+        let times: Vec<NaiveTime> = times
+            .split(',')
+            .map(|time| {
+                NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                    .map_err(|_| ParseRuleError::Time(time.trim().to_owned()))
+            })
+            .try_collect()?;
+        Ok(Rule {
+            weekdays,
+            times: times.into_iter().sorted().dedup().collect(),
+        })
+    }
+}
+
+impl Rule {
+    /// The first time after `after` matching the rule which isn't on a holiday.
+    pub fn next_after(
+        &self,
+        after: DateTime<Tz>,
+        holidays: &HashSet<NaiveDate>,
+    ) -> Option<DateTime<Tz>> {
+        let timezone = after.timezone();
+        let mut date = after.date_naive();
+        for _ in 0..MAX_LOOKAHEAD_DAYS {
+            let weekday = date.weekday().num_days_from_monday() as usize;
+            if self.weekdays[weekday] && !holidays.contains(&date) {
+                // times skipped by a daylight saving change don't happen that day
+                let next = self.times.iter().find_map(|time| {
+                    timezone
+                        .from_local_datetime(&date.and_time(*time))
+                        .earliest()
+                        .filter(|at| *at > after)
+                });
+                if next.is_some() {
+                    return next;
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+/// A clip played in one or more rooms whenever its rule matches.
+pub struct Routine {
+    pub id: i64,
+    pub name: String,
+    pub clip_name: String,
+    pub rooms: Vec<String>,
+    pub rule: String,
+    /// Unix timestamp of the next run, `None` if the rule won't match anymore.
+    pub next_run_at: Option<i64>,
+    pub created_by: i64,
+}
+
+impl Routine {
+    /// Saves a new routine, returns `false` if the name is taken.
+    pub async fn insert(
+        db: &Pool<Sqlite>,
+        name: &str,
+        clip_name: &str,
+        rooms: &[String],
+        rule: &str,
+        next_run_at: Option<i64>,
+        created_by: i64,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let rooms = serde_json::to_string(rooms)?;
+        let res = sqlx::query!(
+            "INSERT INTO routines (name, clip_name, rooms, rule, next_run_at, created_by)
+                VALUES (?, ?, ?, ?, ?, ?)
+                ON CONFLICT(name) DO NOTHING",
+            name,
+            clip_name,
+            rooms,
+            rule,
+            next_run_at,
+            created_by
+        )
+        .execute(db)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Routines due by a unix timestamp or with an id, all of them without either.
+    async fn fetch(
+        db: &Pool<Sqlite>,
+        due_by: Option<i64>,
+        id: Option<i64>,
+    ) -> sqlx::Result<Vec<Routine>> {
+        let rows = sqlx::query!(
+            "SELECT id, name, clip_name, rooms, rule, next_run_at, created_by FROM routines
+                WHERE ($1 IS NULL OR next_run_at <= $1) AND ($2 IS NULL OR id = $2)
+                ORDER BY next_run_at, name",
+            due_by,
+            id
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Routine {
+                id: row.id,
+                name: row.name,
+                clip_name: row.clip_name,
+                rooms: serde_json::from_str(&row.rooms).unwrap_or_else(|err| {
+                    log::error!("invalid rooms of routine {}: {}", row.id, err);
+                    Vec::new()
+                }),
+                rule: row.rule,
+                next_run_at: row.next_run_at,
+                created_by: row.created_by,
+            })
+            .collect())
+    }
+
+    pub async fn all(db: &Pool<Sqlite>) -> sqlx::Result<Vec<Routine>> {
+        Self::fetch(db, None, None).await
+    }
+
+    /// Routines which should have run by the unix timestamp `now`.
+    pub async fn due(db: &Pool<Sqlite>, now: i64) -> sqlx::Result<Vec<Routine>> {
+        Self::fetch(db, Some(now), None).await
+    }
+
+    pub async fn find(db: &Pool<Sqlite>, id: i64) -> sqlx::Result<Option<Routine>> {
+        Ok(Self::fetch(db, None, Some(id)).await?.pop())
+    }
+
+    /// Names of the routines playing a clip.
+    pub async fn using_clip(db: &Pool<Sqlite>, clip_name: &str) -> sqlx::Result<Vec<String>> {
+        let rows = sqlx::query!(
+            "SELECT name FROM routines WHERE clip_name = ? ORDER BY name",
+            clip_name
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.name).collect())
+    }
+
+    pub async fn delete(db: &Pool<Sqlite>, id: i64) -> sqlx::Result<()> {
+        sqlx::query!("DELETE FROM routines WHERE id = ?", id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the routine to its next run after `now`, returning when that is.
+    pub async fn advance(&self, db: &Pool<Sqlite>, now: DateTime<Tz>) -> sqlx::Result<Option<i64>> {
+        let holidays = holidays(db).await?;
+        let next_run_at = match self.rule.parse::<Rule>() {
+            Ok(rule) => rule
+                .next_after(now, &holidays)
+                .map(|next_run| next_run.timestamp()),
+            Err(err) => {
+                log::error!("invalid rule of routine {}: {}", self.name, err);
+                None
+            }
+        };
+        sqlx::query!(
+            "UPDATE routines SET next_run_at = ? WHERE id = ?",
+            next_run_at,
+            self.id
+        )
+        .execute(db)
+        .await?;
+        Ok(next_run_at)
+    }
+}
+
+/// Recalculates all runs, i. e. after the holidays changed.
+pub async fn reschedule_routines(db: &Pool<Sqlite>, now: DateTime<Tz>) -> sqlx::Result<()> {
+    for routine in Routine::all(db).await? {
+        routine.advance(db, now).await?;
+    }
+    Ok(())
+}
+
+/// Dates on which no routine plays.
+pub async fn holidays(db: &Pool<Sqlite>) -> sqlx::Result<HashSet<NaiveDate>> {
+    let rows = sqlx::query!("SELECT date FROM holidays")
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| NaiveDate::parse_from_str(&row.date, "%Y-%m-%d").ok())
+        .collect())
+}
+
+pub async fn add_holiday(db: &Pool<Sqlite>, date: NaiveDate) -> sqlx::Result<()> {
+    let date = date.format("%Y-%m-%d").to_string();
+    sqlx::query!(
+        "INSERT INTO holidays (date) VALUES (?) ON CONFLICT(date) DO NOTHING",
+        date
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_holiday(db: &Pool<Sqlite>, date: &str) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM holidays WHERE date = ?", date)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+    use crate::db;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, min: u32) -> DateTime<Tz> {
+        Berlin
+            .with_ymd_and_hms(year, month, day, hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule: Rule = "Mon-Fri 12:30,08:00".parse().unwrap();
+        assert_eq!(rule.weekdays, [true, true, true, true, true, false, false]);
+        assert_eq!(
+            rule.times,
+            [
+                NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(12, 30, 0).unwrap()
+            ]
+        );
+        let rule: Rule = "fri-mon,wed 07:45".parse().unwrap();
+        assert_eq!(rule.weekdays, [true, false, true, false, true, true, true]);
+        assert_eq!(
+            "weekends 10:00".parse::<Rule>().unwrap().weekdays,
+            [false, false, false, false, false, true, true]
+        );
+        assert_eq!("daily".parse::<Rule>(), Err(ParseRuleError::Format));
+        assert_eq!(
+            "Mon-Fry 08:00".parse::<Rule>(),
+            Err(ParseRuleError::Day("Fry".into()))
+        );
+        assert_eq!(
+            "daily 25:00".parse::<Rule>(),
+            Err(ParseRuleError::Time("25:00".into()))
+        );
+    }
+
+    #[test]
+    fn next_run_skips_weekends_and_holidays() {
+        let rule: Rule = "weekdays 08:00,13:15".parse().unwrap();
+        let no_holidays = HashSet::new();
+        // 2024-05-03 is a friday
+        assert_eq!(
+            rule.next_after(at(2024, 5, 3, 8, 0), &no_holidays),
+            Some(at(2024, 5, 3, 13, 15))
+        );
+        assert_eq!(
+            rule.next_after(at(2024, 5, 3, 13, 15), &no_holidays),
+            Some(at(2024, 5, 6, 8, 0))
+        );
+        let holidays = HashSet::from([NaiveDate::from_ymd_opt(2024, 5, 6).unwrap()]);
+        assert_eq!(
+            rule.next_after(at(2024, 5, 3, 13, 15), &holidays),
+            Some(at(2024, 5, 7, 8, 0))
+        );
+    }
+
+    #[test]
+    fn next_run_skips_missing_local_times() {
+        // clocks jump from 02:00 to 03:00 on 2024-03-31 in Berlin
+        let rule: Rule = "daily 02:30".parse().unwrap();
+        assert_eq!(
+            rule.next_after(at(2024, 3, 30, 12, 0), &HashSet::new()),
+            Some(at(2024, 4, 1, 2, 30))
+        );
+    }
+
+    #[tokio::test]
+    async fn due_routines_advance() {
+        let db = db::memory().await;
+
+        let rooms = ["Hall".to_owned()];
+        let first_run = at(2024, 5, 3, 8, 0).timestamp();
+        Routine::insert(
+            &db,
+            "bell",
+            "Bell",
+            &rooms,
+            "Mon-Fri 08:00",
+            Some(first_run),
+            1,
+        )
+        .await
+        .unwrap();
+        assert!(Routine::due(&db, first_run - 1).await.unwrap().is_empty());
+
+        let due = Routine::due(&db, first_run).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].rooms, rooms);
+
+        add_holiday(&db, NaiveDate::from_ymd_opt(2024, 5, 6).unwrap())
+            .await
+            .unwrap();
+        let next_run = due[0].advance(&db, at(2024, 5, 3, 8, 0)).await.unwrap();
+        assert_eq!(next_run, Some(at(2024, 5, 7, 8, 0).timestamp()));
+        assert!(Routine::due(&db, first_run + 3600)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
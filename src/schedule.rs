@@ -0,0 +1,170 @@
+use chrono::{DateTime, Days, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use sqlx::{Pool, Sqlite};
+
+/// An audio to play in a room at a given time, deleted once it ran.
+pub struct Schedule {
+    pub id: i64,
+    pub unique_id: String,
+    pub room_name: String,
+    /// Unix timestamp of when to play the audio.
+    pub run_at: i64,
+    /// The user who is notified when the job ran.
+    pub created_by: i64,
+}
+
+impl Schedule {
+    pub async fn insert(
+        db: &Pool<Sqlite>,
+        unique_id: &str,
+        room_name: &str,
+        run_at: i64,
+        created_by: i64,
+    ) -> sqlx::Result<i64> {
+        let res = sqlx::query!(
+            "INSERT INTO schedules (unique_id, room_name, run_at, created_by) VALUES (?, ?, ?, ?)",
+            unique_id,
+            room_name,
+            run_at,
+            created_by
+        )
+        .execute(db)
+        .await?;
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Whether the audio is already scheduled in the room at this time, i. e. by an earlier import.
+    pub async fn exists(
+        db: &Pool<Sqlite>,
+        unique_id: &str,
+        room_name: &str,
+        run_at: i64,
+    ) -> sqlx::Result<bool> {
+        let row = sqlx::query!(
+            "SELECT id FROM schedules WHERE unique_id = ? AND room_name = ? AND run_at = ?",
+            unique_id,
+            room_name,
+            run_at
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(row.is_some())
+    }
+
+    pub async fn all(db: &Pool<Sqlite>) -> sqlx::Result<Vec<Schedule>> {
+        sqlx::query_as!(
+            Schedule,
+            "SELECT id, unique_id, room_name, run_at, created_by FROM schedules ORDER BY run_at"
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// The jobs a user scheduled, the next one first.
+    pub async fn created_by(db: &Pool<Sqlite>, user_id: i64) -> sqlx::Result<Vec<Schedule>> {
+        sqlx::query_as!(
+            Schedule,
+            "SELECT id, unique_id, room_name, run_at, created_by FROM schedules
+                WHERE created_by = ? ORDER BY run_at",
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Jobs which should have run by the unix timestamp `now`, the oldest first.
+    pub async fn due(db: &Pool<Sqlite>, now: i64) -> sqlx::Result<Vec<Schedule>> {
+        sqlx::query_as!(
+            Schedule,
+            "SELECT id, unique_id, room_name, run_at, created_by FROM schedules
+                WHERE run_at <= ? ORDER BY run_at",
+            now
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn find(db: &Pool<Sqlite>, id: i64) -> sqlx::Result<Option<Schedule>> {
+        sqlx::query_as!(
+            Schedule,
+            "SELECT id, unique_id, room_name, run_at, created_by FROM schedules WHERE id = ?",
+            id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    pub async fn delete(db: &Pool<Sqlite>, id: i64) -> sqlx::Result<()> {
+        sqlx::query!("DELETE FROM schedules WHERE id = ?", id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Parses `14:00` as the next time it is 14:00, or a full date like `2024-05-01 14:00`.
+/// Returns `None` for invalid or past times.
+pub fn parse_time(text: &str, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let text = text.trim();
+    let timezone = now.timezone();
+    let time = match NaiveTime::parse_from_str(text, "%H:%M") {
+        Ok(time) => {
+            let today = timezone
+                .from_local_datetime(&now.date_naive().and_time(time))
+                .earliest()?;
+            match today > now {
+                true => today,
+                false => timezone
+                    .from_local_datetime(
+                        &now.date_naive()
+                            .checked_add_days(Days::new(1))?
+                            .and_time(time),
+                    )
+                    .earliest()?,
+            }
+        }
+        Err(_) => {
+            let date_time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").ok()?;
+            timezone.from_local_datetime(&date_time).earliest()?
+        }
+    };
+    (time > now).then_some(time)
+}
+
+pub fn format_time(timestamp: i64, timezone: Tz) -> String {
+    match timezone.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn now() -> DateTime<Tz> {
+        Berlin.with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_times() {
+        let at = |text| parse_time(text, now()).map(|time| time.naive_local().to_string());
+        assert_eq!(at("14:00").as_deref(), Some("2024-05-01 14:00:00"));
+        assert_eq!(at(" 09:15 ").as_deref(), Some("2024-05-02 09:15:00"));
+        assert_eq!(
+            at("2024-06-10 08:00").as_deref(),
+            Some("2024-06-10 08:00:00")
+        );
+        assert_eq!(at("2024-04-30 08:00"), None);
+        assert_eq!(at("noon"), None);
+    }
+
+    #[test]
+    fn format_in_timezone() {
+        let timestamp = now().timestamp();
+        assert_eq!(format_time(timestamp, Berlin), "2024-05-01 12:30");
+        assert_eq!(format_time(timestamp, Tz::UTC), "2024-05-01 10:30");
+    }
+}
@@ -0,0 +1,426 @@
+use std::{error::Error, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use sqlx::{Pool, Sqlite};
+use teloxide::{requests::Requester, types::ChatId, Bot};
+use tokio::time;
+
+use crate::{
+    announcement::{prepare_audio, Room},
+    audio::processor::AudioProcessor,
+    audio_cache::AudioCacheEntry,
+    clip::Clip,
+    config::AppConfig,
+    player::{Player, PlayerLock, Repeat},
+    routine::Routine,
+    schedule::{format_time, Schedule},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Jobs which can't start within this time, i. e. because another audio is playing or the bot
+/// was down, are dropped instead of surprising everyone later on.
+const MAX_LATENESS: Duration = Duration::from_secs(15 * 60);
+
+/// What becomes of a job or routine run which is due.
+#[derive(Debug, PartialEq)]
+enum Decision {
+    /// the player is busy, try again on the next tick
+    Wait,
+    Skip,
+    Play,
+}
+
+/// How playing an audio in a room went.
+enum Outcome {
+    Played,
+    Stopped(String),
+    AudioGone,
+    RoomGone,
+}
+
+/// Due runs wait for a busy player until they are too late, then they are skipped even if the
+/// player is free.
+fn decide(run_at: i64, now: i64, player_free: bool) -> Decision {
+    if now - run_at >= MAX_LATENESS.as_secs() as i64 {
+        Decision::Skip
+    } else if player_free {
+        Decision::Play
+    } else {
+        Decision::Wait
+    }
+}
+
+/// Takes a due job off the schedule unless it waits for the player, jobs only run once even if
+/// they fail.
+async fn take_job(
+    db: &Pool<Sqlite>,
+    job: &Schedule,
+    now: i64,
+    player_free: bool,
+) -> sqlx::Result<Decision> {
+    let decision = decide(job.run_at, now, player_free);
+    if decision != Decision::Wait {
+        Schedule::delete(db, job.id).await?;
+    }
+    Ok(decision)
+}
+
+/// Moves a due routine on to its next run unless it waits for the player. This happens before
+/// it plays, so a failing routine doesn't repeat every tick.
+async fn take_routine(
+    db: &Pool<Sqlite>,
+    routine: &Routine,
+    now: DateTime<Tz>,
+    player_free: bool,
+) -> sqlx::Result<Decision> {
+    let decision = decide(
+        routine.next_run_at.unwrap_or_default(),
+        now.timestamp(),
+        player_free,
+    );
+    if decision != Decision::Wait {
+        routine.advance(db, now).await?;
+    }
+    Ok(decision)
+}
+
+pub struct Scheduler {
+    bot: Bot,
+    db: Pool<Sqlite>,
+    app_config: Arc<AppConfig>,
+    player: Arc<Player>,
+    audio_processor: Arc<AudioProcessor>,
+    /// Read again before every job and routine, as playing one may take minutes.
+    clock: Box<dyn Fn() -> DateTime<Tz> + Send + Sync>,
+}
+
+impl Scheduler {
+    pub fn new(
+        bot: Bot,
+        db: Pool<Sqlite>,
+        app_config: Arc<AppConfig>,
+        player: Arc<Player>,
+        audio_processor: Arc<AudioProcessor>,
+    ) -> Scheduler {
+        let timezone = app_config.timezone;
+        Scheduler {
+            bot,
+            db,
+            app_config,
+            player,
+            audio_processor,
+            clock: Box::new(move || Utc::now().with_timezone(&timezone)),
+        }
+    }
+
+    pub async fn task(self) {
+        let mut interval = time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            self.tick().await;
+        }
+    }
+
+    /// Runs everything due by now.
+    async fn tick(&self) {
+        match Schedule::due(&self.db, (self.clock)().timestamp()).await {
+            Ok(jobs) => {
+                for job in jobs {
+                    self.dispatch(job).await;
+                }
+            }
+            Err(err) => log::error!("failed to load scheduled jobs: {}", err),
+        }
+
+        match Routine::due(&self.db, (self.clock)().timestamp()).await {
+            Ok(routines) => {
+                for routine in routines {
+                    self.dispatch_routine(routine).await;
+                }
+            }
+            Err(err) => log::error!("failed to load routines: {}", err),
+        }
+    }
+
+    async fn dispatch(&self, job: Schedule) {
+        let now = (self.clock)();
+        let player_lock = self.player.try_lock().ok();
+        let decision = match take_job(&self.db, &job, now.timestamp(), player_lock.is_some()).await
+        {
+            Ok(decision) => decision,
+            Err(err) => {
+                log::error!("failed to delete scheduled job {}: {}", job.id, err);
+                return;
+            }
+        };
+
+        let text = match (decision, player_lock) {
+            (Decision::Wait, _) => return,
+            (Decision::Play, Some(player_lock)) => {
+                match self.play(&job.unique_id, &job.room_name, player_lock).await {
+                    Ok(Outcome::Played) => {
+                        format!("Played the scheduled audio in: {}", job.room_name)
+                    }
+                    Ok(Outcome::Stopped(stopped_by)) => format!(
+                        "Stopped the scheduled audio in: {} by {}",
+                        job.room_name, stopped_by
+                    ),
+                    Ok(Outcome::AudioGone) => format!(
+                        "The scheduled audio in: {} is no longer available.",
+                        job.room_name
+                    ),
+                    Ok(Outcome::RoomGone) => format!(
+                        "The room {} of the scheduled audio no longer exists.",
+                        job.room_name
+                    ),
+                    Err(err) => {
+                        log::error!("scheduled job {} failed: {}", job.id, err);
+                        format!(
+                            "Failed to play the scheduled audio in: {} :/",
+                            job.room_name
+                        )
+                    }
+                }
+            }
+            _ => format!(
+                "Skipped the scheduled audio in: {}, it was due at {} ({}) and couldn't start in time.",
+                job.room_name,
+                format_time(job.run_at, self.app_config.timezone),
+                self.app_config.timezone
+            ),
+        };
+        if let Err(err) = self.bot.send_message(ChatId(job.created_by), text).await {
+            log::warn!("failed to notify about scheduled job {}: {}", job.id, err);
+        }
+    }
+
+    async fn dispatch_routine(&self, routine: Routine) {
+        let now = (self.clock)();
+        let mut player_lock = self.player.try_lock().ok();
+        match take_routine(&self.db, &routine, now, player_lock.is_some()).await {
+            Ok(Decision::Play) => {}
+            Ok(Decision::Wait) => return,
+            Ok(Decision::Skip) => {
+                let run_at = routine.next_run_at.unwrap_or_default();
+                log::warn!(
+                    "skipped routine {}, it is {} s late",
+                    routine.name,
+                    now.timestamp() - run_at
+                );
+                let text = format!(
+                    "Routine {} skipped its run at {} ({}), it couldn't start in time.",
+                    routine.name,
+                    format_time(run_at, self.app_config.timezone),
+                    self.app_config.timezone
+                );
+                if let Err(err) = self
+                    .bot
+                    .send_message(ChatId(routine.created_by), text)
+                    .await
+                {
+                    log::warn!("failed to notify about routine {}: {}", routine.name, err);
+                }
+                return;
+            }
+            Err(err) => {
+                log::error!("failed to advance routine {}: {}", routine.name, err);
+                return;
+            }
+        }
+
+        let mut failures = Vec::new();
+        match Clip::find(&self.db, &routine.clip_name).await {
+            Ok(Some(clip)) => {
+                // the rooms play one after another, as there is a single player
+                for room_name in &routine.rooms {
+                    let Some(player_lock) =
+                        player_lock.take().or_else(|| self.player.try_lock().ok())
+                    else {
+                        failures.push(format!("{}: another audio was playing", room_name));
+                        continue;
+                    };
+                    match self.play(&clip.unique_id, room_name, player_lock).await {
+                        Ok(Outcome::Played) => {}
+                        Ok(Outcome::Stopped(stopped_by)) => {
+                            failures.push(format!("{}: stopped by {}", room_name, stopped_by));
+                            break;
+                        }
+                        Ok(Outcome::AudioGone) => {
+                            failures.push("the audio of the clip is no longer available".into());
+                            break;
+                        }
+                        Ok(Outcome::RoomGone) => {
+                            failures.push(format!("{}: the room no longer exists", room_name))
+                        }
+                        Err(err) => {
+                            log::error!(
+                                "routine {} failed in {}: {}",
+                                routine.name,
+                                room_name,
+                                err
+                            );
+                            failures.push(format!("{}: failed to play", room_name));
+                        }
+                    }
+                }
+            }
+            Ok(None) => failures.push(format!("the clip {} no longer exists", routine.clip_name)),
+            Err(err) => {
+                log::error!("failed to load clip of routine {}: {}", routine.name, err);
+                failures.push("failed to load the clip".into());
+            }
+        }
+
+        // routines run all the time, so only problems are worth a message
+        if failures.is_empty() {
+            return;
+        }
+        let text = format!(
+            "Routine {} didn't play as planned:\n{}",
+            routine.name,
+            failures.iter().join("\n")
+        );
+        if let Err(err) = self
+            .bot
+            .send_message(ChatId(routine.created_by), text)
+            .await
+        {
+            log::warn!("failed to notify about routine {}: {}", routine.name, err);
+        }
+    }
+
+    async fn play(
+        &self,
+        unique_id: &str,
+        room_name: &str,
+        player_lock: PlayerLock<'_>,
+    ) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let Some(entry) = AudioCacheEntry::find(&self.db, unique_id).await? else {
+            return Ok(Outcome::AudioGone);
+        };
+        let Some(room) = Room::find(&self.db, room_name).await? else {
+            return Ok(Outcome::RoomGone);
+        };
+
+        let (_, processed) = prepare_audio(
+            &self.bot,
+            &self.db,
+            &self.app_config,
+            &self.audio_processor,
+            &entry,
+            &room,
+        )
+        .await?;
+        if self.app_config.env.mock_ahm_connection {
+            log::warn!("Skipping preset config because MOCK_AHM_CONNECTION is enabled.");
+        } else {
+            self.player.set_channel(room.preset as u16).await?;
+        }
+
+        let audio_path = processed
+            .path
+            .to_str()
+            .ok_or("failed to construct voice file path")?;
+        let report = player_lock
+            .play_audio_file(audio_path, Repeat::default(), &room.overrides)
+            .await?;
+        Ok(match report.stopped_by {
+            Some(stopped_by) => Outcome::Stopped(stopped_by),
+            None => Outcome::Played,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+    use crate::db;
+
+    #[test]
+    fn late_runs_are_skipped() {
+        let late = MAX_LATENESS.as_secs() as i64;
+        assert_eq!(decide(100, 100, true), Decision::Play);
+        assert_eq!(decide(100, 100 + late - 1, false), Decision::Wait);
+        assert_eq!(decide(100, 100 + late, false), Decision::Skip);
+        // i. e. the bot was down
+        assert_eq!(decide(100, 100 + 24 * 3600, true), Decision::Skip);
+    }
+
+    #[tokio::test]
+    async fn jobs_wait_for_the_player_until_too_late() {
+        let db = db::memory().await;
+        let run_at = Berlin
+            .with_ymd_and_hms(2024, 5, 3, 8, 0, 0)
+            .unwrap()
+            .timestamp();
+        let id = Schedule::insert(&db, "audio", "Hall", run_at, 1)
+            .await
+            .unwrap();
+        let job = Schedule::find(&db, id).await.unwrap().unwrap();
+
+        let decision = take_job(&db, &job, run_at + 60, false).await.unwrap();
+        assert_eq!(decision, Decision::Wait);
+        assert!(Schedule::find(&db, id).await.unwrap().is_some());
+
+        let too_late = run_at + MAX_LATENESS.as_secs() as i64;
+        let decision = take_job(&db, &job, too_late, true).await.unwrap();
+        assert_eq!(decision, Decision::Skip);
+        assert!(Schedule::find(&db, id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn routines_advance_before_playing() {
+        let db = db::memory().await;
+        let at = |day: u32, hour: u32, min: u32| {
+            Berlin.with_ymd_and_hms(2024, 5, day, hour, min, 0).unwrap()
+        };
+        // 2024-05-03 is a friday
+        Routine::insert(
+            &db,
+            "bell",
+            "Bell",
+            &["Hall".to_owned()],
+            "Mon-Fri 08:00",
+            Some(at(3, 8, 0).timestamp()),
+            1,
+        )
+        .await
+        .unwrap();
+        let routine = Routine::all(&db).await.unwrap().remove(0);
+        let next_run_at = |db| async move {
+            Routine::find(db, routine.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .next_run_at
+        };
+
+        let decision = take_routine(&db, &routine, at(3, 8, 1), false)
+            .await
+            .unwrap();
+        assert_eq!(decision, Decision::Wait);
+        assert_eq!(next_run_at(&db).await, Some(at(3, 8, 0).timestamp()));
+
+        let decision = take_routine(&db, &routine, at(3, 8, 2), true)
+            .await
+            .unwrap();
+        assert_eq!(decision, Decision::Play);
+        assert_eq!(next_run_at(&db).await, Some(at(6, 8, 0).timestamp()));
+
+        // the bot was down over the weekend
+        let routine = Routine::find(&db, routine.id).await.unwrap().unwrap();
+        let decision = take_routine(&db, &routine, at(6, 11, 0), true)
+            .await
+            .unwrap();
+        assert_eq!(decision, Decision::Skip);
+        assert_eq!(next_run_at(&db).await, Some(at(7, 8, 0).timestamp()));
+    }
+}
@@ -0,0 +1,118 @@
+use sqlx::{Pool, Sqlite};
+
+/// An announcement text with `{variable}` placeholders, filled in before it is read out.
+pub struct Template {
+    pub name: String,
+    pub text: String,
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+fn is_variable(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Splits the text at placeholders, braces around anything else are kept as they are.
+fn parts(mut text: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    while let Some(start) = text.find('{') {
+        let Some(len) = text[start + 1..].find('}') else {
+            break;
+        };
+        let name = &text[start + 1..start + 1 + len];
+        if is_variable(name) {
+            parts.push(Part::Text(&text[..start]));
+            parts.push(Part::Variable(name));
+        } else {
+            parts.push(Part::Text(&text[..start + 1]));
+            text = &text[start + 1..];
+            continue;
+        }
+        text = &text[start + len + 2..];
+    }
+    parts.push(Part::Text(text));
+    parts
+}
+
+impl Template {
+    /// The names of all placeholders in order of their first appearance.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        for part in parts(&self.text) {
+            if let Part::Variable(name) = part {
+                if !variables.contains(&name) {
+                    variables.push(name);
+                }
+            }
+        }
+        variables
+    }
+
+    /// Replaces the placeholders with values given in the order of [`Template::variables`].
+    pub fn render(&self, values: &[String]) -> String {
+        let variables = self.variables();
+        parts(&self.text)
+            .into_iter()
+            .map(|part| match part {
+                Part::Text(text) => text,
+                Part::Variable(name) => variables
+                    .iter()
+                    .position(|variable| *variable == name)
+                    .and_then(|index| values.get(index))
+                    .map_or(name, String::as_str),
+            })
+            .collect()
+    }
+
+    pub async fn find(db: &Pool<Sqlite>, name: &str) -> sqlx::Result<Option<Template>> {
+        sqlx::query_as!(
+            Template,
+            "SELECT name, text FROM templates WHERE name = ?",
+            name
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    pub async fn all(db: &Pool<Sqlite>) -> sqlx::Result<Vec<Template>> {
+        sqlx::query_as!(Template, "SELECT name, text FROM templates ORDER BY name")
+            .fetch_all(db)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(text: &str) -> Template {
+        Template {
+            name: "test".into(),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn variables_in_order() {
+        let template = template("The {event} in {room} starts in {minutes} minutes, {room}!");
+        assert_eq!(template.variables(), ["event", "room", "minutes"]);
+        assert!(self::template("No {placeholders here} {}")
+            .variables()
+            .is_empty());
+    }
+
+    #[test]
+    fn render_values() {
+        let template = template("The meeting in {room} starts in {minutes} minutes {:)}");
+        assert_eq!(
+            template.render(&["Hall".into(), "5".into()]),
+            "The meeting in Hall starts in 5 minutes {:)}"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use std::{io, process::Stdio};
+
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+use thiserror::Error;
+use tokio::{fs, io::AsyncWriteExt, process::Command, task};
+
+use crate::{
+    audio::probe::probe_file,
+    audio_cache::{checksum, temp_path, AudioCacheEntry},
+    config::AppConfig,
+};
+
+#[derive(Error, Debug)]
+pub enum SynthesizeError {
+    #[error("text-to-speech isn't configured")]
+    NotConfigured,
+    #[error("tts command parse error")]
+    CommandParse,
+    #[error("tts command exited unsuccessfully")]
+    Failed,
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to update audio cache: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("synthesizing task panicked")]
+    Join(#[from] task::JoinError),
+}
+
+/// A text to announce. It is kept next to the synthesized audio, so it can be read out again in
+/// the voice of another room or if the file got lost.
+pub struct Speech {
+    pub text: String,
+    /// The voice picked for this announcement, rooms use their own voice otherwise.
+    pub voice: Option<String>,
+}
+
+impl Speech {
+    /// Parses a message, a leading `voice:` picks one of the configured voices.
+    pub fn parse(text: &str, voices: &[String]) -> Speech {
+        if let Some((voice, rest)) = text.split_once(':') {
+            let voice = voice.trim();
+            if voices.iter().any(|known| known == voice) {
+                return Speech {
+                    text: rest.trim().to_owned(),
+                    voice: Some(voice.to_owned()),
+                };
+            }
+        }
+        Speech {
+            text: text.trim().to_owned(),
+            voice: None,
+        }
+    }
+
+    pub async fn find(db: &Pool<Sqlite>, unique_id: &str) -> sqlx::Result<Option<Speech>> {
+        let row = sqlx::query!(
+            "SELECT text, voice FROM speech WHERE unique_id = ?",
+            unique_id
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(row.map(|row| Speech {
+            text: row.text,
+            voice: row.voice,
+        }))
+    }
+}
+
+/// Audio cache ids of speech depend on text and voice, so repeated announcements share a file.
+fn speech_id(text: &str, voice: &str) -> String {
+    let hash = Sha256::new()
+        .chain_update(voice)
+        .chain_update(b"\0")
+        .chain_update(text)
+        .finalize();
+    format!("tts-{:x}", hash)[..20].to_owned()
+}
+
+/// Returns the speech read out in the voice of the announcement, the room or the default voice,
+/// running the tts command unless it was synthesized before.
+pub async fn synthesize(
+    db: &Pool<Sqlite>,
+    app_config: &AppConfig,
+    speech: &Speech,
+    room_voice: Option<&str>,
+) -> Result<AudioCacheEntry, SynthesizeError> {
+    let audio_dir = &app_config.audio_dir;
+    let requested_voice = speech.voice.as_deref().or(room_voice);
+    let voice = requested_voice.unwrap_or(&app_config.env.tts_voice);
+    let unique_id = speech_id(&speech.text, voice);
+
+    if let Some(entry) = AudioCacheEntry::find(db, &unique_id).await? {
+        if entry.is_present(audio_dir).await {
+            entry.touch(db).await?;
+            return Ok(entry);
+        }
+        log::warn!(
+            "cached speech {} is missing or truncated, synthesizing it again",
+            entry.file_name
+        );
+        entry.remove(db, audio_dir).await?;
+    }
+
+    let tts_command = app_config
+        .env
+        .tts_command
+        .as_ref()
+        .ok_or(SynthesizeError::NotConfigured)?;
+    let file_name = format!("{}.wav", unique_id);
+    let dst_path = audio_dir.join(&file_name);
+    let tmp_path = temp_path(audio_dir, &file_name);
+    let mut args = shell_words::split(tts_command)
+        .map_err(|_| SynthesizeError::CommandParse)?
+        .into_iter()
+        .map(|arg| match arg.as_str() {
+            "%o" => tmp_path.to_string_lossy().into_owned(),
+            _ => arg.replace("%v", voice),
+        });
+    let program = args.next().ok_or(SynthesizeError::CommandParse)?;
+
+    // the text is passed on stdin, so it is neither escaped nor limited by the argument length
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(speech.text.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        log::warn!(
+            "tts command stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(SynthesizeError::Failed);
+    }
+
+    let size = fs::metadata(&tmp_path).await?.len() as i64;
+    let checksum = checksum(&tmp_path).await?;
+    fs::rename(&tmp_path, &dst_path).await?;
+
+    let mut entry = AudioCacheEntry {
+        unique_id,
+        file_id: String::new(),
+        file_name,
+        size,
+        duration_ms: None,
+        checksum,
+    };
+    entry.insert(db).await?;
+    sqlx::query!(
+        "INSERT INTO speech (unique_id, text, voice) VALUES (?, ?, ?)
+            ON CONFLICT(unique_id) DO NOTHING",
+        entry.unique_id,
+        speech.text,
+        requested_voice
+    )
+    .execute(db)
+    .await?;
+
+    if let Ok(info) = task::spawn_blocking(move || probe_file(&dst_path)).await? {
+        if let Some(duration) = info.duration {
+            entry.set_duration(db, duration).await?;
+        }
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_voice_prefix() {
+        let voices = ["de".to_owned(), "en-us".to_owned()];
+
+        let speech = Speech::parse("de: Guten Morgen", &voices);
+        assert_eq!(speech.voice.as_deref(), Some("de"));
+        assert_eq!(speech.text, "Guten Morgen");
+
+        let speech = Speech::parse("Note: dinner is ready ", &voices);
+        assert_eq!(speech.voice, None);
+        assert_eq!(speech.text, "Note: dinner is ready");
+    }
+
+    #[test]
+    fn speech_ids_differ_by_voice() {
+        let id = speech_id("Hello", "en");
+        assert!(id.starts_with("tts-"));
+        assert_eq!(id, speech_id("Hello", "en"));
+        assert_ne!(id, speech_id("Hello", "de"));
+    }
+}